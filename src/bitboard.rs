@@ -0,0 +1,275 @@
+use std::sync::OnceLock;
+
+use crate::fairy::{self, MAX_PIECES};
+use crate::{Piece, PieceType};
+
+//Squares per rank in a bitboard, room enough for the widest board
+pub const FILES: usize = 16;
+
+/*
+Bit n is square n counting from a1 along the files, sixteen to a rank so boards wider than eight
+files fit. Rows in piece positions start on black's side, so they are mirrored. Boards with fewer
+ranks leave the top ones empty.
+*/
+pub fn square_index(square: (u8, u8)) -> usize {
+    (7 - square.1 as usize) * FILES + square.0 as usize
+}
+
+pub fn index_square(index: usize) -> (u8, u8) {
+    ((index % FILES) as u8, 7 - (index / FILES) as u8)
+}
+
+//The square's number with eight to a rank, as Polyglot and Syzygy count them on the usual board
+pub fn index_64(index: usize) -> usize {
+    index / FILES * 8 + index % FILES
+}
+
+pub fn bit(square: (u8, u8)) -> u128 {
+    1 << square_index(square)
+}
+
+const KINDS: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+//Pieces from a pieces file come after the usual six
+fn kind_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Fairy(i) => KINDS.len() + i as usize,
+        v => KINDS.iter().position(|&x| x == v).unwrap(),
+    }
+}
+
+fn kind_at(index: usize) -> PieceType {
+    KINDS
+        .get(index)
+        .copied()
+        .unwrap_or_else(|| PieceType::Fairy((index - KINDS.len()) as u8))
+}
+
+fn team_index(team_white: bool) -> usize {
+    if team_white {
+        0
+    } else {
+        1
+    }
+}
+
+//Indexes of the set bits, lowest first
+pub fn squares(mut bitboard: u128) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let index = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(index)
+    })
+}
+
+//Pieces split up by team and by type, so a square's piece is the intersection of the two
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Bitboards {
+    teams: [u128; 2],
+    kinds: [u128; 6 + MAX_PIECES],
+}
+
+impl Bitboards {
+    pub fn from_pieces(pieces: &[Piece]) -> Self {
+        let mut bitboards = Bitboards::default();
+        for piece in pieces {
+            bitboards.toggle(
+                piece.team_white,
+                piece.piece_type,
+                square_index(piece.position),
+            );
+        }
+        bitboards
+    }
+
+    //Puts the piece on the square, or takes it off if it's already there
+    pub fn toggle(&mut self, team_white: bool, piece_type: PieceType, square: usize) {
+        self.teams[team_index(team_white)] ^= 1 << square;
+        self.kinds[kind_index(piece_type)] ^= 1 << square;
+    }
+
+    pub fn team(&self, team_white: bool) -> u128 {
+        self.teams[team_index(team_white)]
+    }
+
+    pub fn occupied(&self) -> u128 {
+        self.teams[0] | self.teams[1]
+    }
+
+    pub fn pieces(&self, team_white: bool, piece_type: PieceType) -> u128 {
+        self.team(team_white) & self.kinds[kind_index(piece_type)]
+    }
+
+    pub fn piece_at(&self, square: usize) -> Option<(bool, PieceType)> {
+        let mask = 1 << square;
+        if self.occupied() & mask == 0 {
+            return None;
+        }
+
+        let kind = self.kinds.iter().position(|x| x & mask != 0)?;
+        Some((self.teams[0] & mask != 0, kind_at(kind)))
+    }
+
+    pub fn king(&self, team_white: bool) -> Option<usize> {
+        squares(self.pieces(team_white, PieceType::King)).next()
+    }
+
+    pub fn is_attacked(&self, square: usize, by_white: bool) -> bool {
+        let occupied = self.occupied();
+        let pieces = |piece_type| self.pieces(by_white, piece_type);
+        let queens = pieces(PieceType::Queen);
+
+        //Attacks are symmetric, so look outwards from the square with each kind of piece
+        pawn_attacks(!by_white, square) & pieces(PieceType::Pawn) != 0
+            || knight_attacks(square) & pieces(PieceType::Knight) != 0
+            || king_attacks(square) & pieces(PieceType::King) != 0
+            || bishop_attacks(square, occupied) & (pieces(PieceType::Bishop) | queens) != 0
+            || rook_attacks(square, occupied) & (pieces(PieceType::Rook) | queens) != 0
+            || self.fairy_attacks(square, by_white)
+    }
+
+    //Hoppers don't attack alike both ways, so each piece is looked at from where it stands
+    fn fairy_attacks(&self, square: usize, by_white: bool) -> bool {
+        let occupied = self.occupied();
+        (0..fairy::pieces().len()).any(|i| {
+            squares(self.pieces(by_white, PieceType::Fairy(i as u8)))
+                .any(|from| fairy::attacks(i as u8, from, occupied) & 1 << square != 0)
+        })
+    }
+}
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+//The first four run towards higher squares, so the nearest piece on them is the lowest set bit
+const DIRECTIONS: [(i8, i8); 8] = [
+    (1, 0),
+    (0, 1),
+    (1, 1),
+    (-1, 1),
+    (-1, 0),
+    (0, -1),
+    (-1, -1),
+    (1, -1),
+];
+const ROOK_DIRECTIONS: [usize; 4] = [0, 1, 4, 5];
+const BISHOP_DIRECTIONS: [usize; 4] = [2, 3, 6, 7];
+
+const SQUARES: usize = FILES * 8;
+
+struct Tables {
+    knight: [u128; SQUARES],
+    king: [u128; SQUARES],
+    //White's pawn attacks first
+    pawn: [[u128; SQUARES]; 2],
+    //Every square out to the edge in each direction, by the order of DIRECTIONS
+    rays: [[u128; SQUARES]; 8],
+}
+
+fn on_board(file: i8, rank: i8) -> bool {
+    (0..FILES as i8).contains(&file) && (0..8).contains(&rank)
+}
+
+//Squares reached by single steps in the given directions, files and ranks counted from a1
+fn steps(square: usize, offsets: &[(i8, i8)]) -> u128 {
+    let (file, rank) = ((square % FILES) as i8, (square / FILES) as i8);
+    offsets
+        .iter()
+        .map(|(x, y)| (file + x, rank + y))
+        .filter(|&(x, y)| on_board(x, y))
+        .fold(0, |bitboard, (x, y)| {
+            bitboard | 1 << (y as usize * FILES + x as usize)
+        })
+}
+
+fn ray(square: usize, (x, y): (i8, i8)) -> u128 {
+    let (mut file, mut rank) = ((square % FILES) as i8, (square / FILES) as i8);
+    let mut ray = 0;
+    loop {
+        file += x;
+        rank += y;
+        if !on_board(file, rank) {
+            break ray;
+        }
+        ray |= 1 << (rank as usize * FILES + file as usize);
+    }
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| Tables {
+        knight: std::array::from_fn(|i| steps(i, &KNIGHT_STEPS)),
+        king: std::array::from_fn(|i| steps(i, &KING_STEPS)),
+        pawn: [
+            std::array::from_fn(|i| steps(i, &[(-1, 1), (1, 1)])),
+            std::array::from_fn(|i| steps(i, &[(-1, -1), (1, -1)])),
+        ],
+        rays: std::array::from_fn(|d| std::array::from_fn(|i| ray(i, DIRECTIONS[d]))),
+    })
+}
+
+//Each ray is cut off past the first piece on it, which is included as it may be struck
+fn slide(square: usize, occupied: u128, directions: [usize; 4]) -> u128 {
+    let rays = &tables().rays;
+    directions.into_iter().fold(0, |attacks, d| {
+        let ray = rays[d][square];
+        let blockers = ray & occupied;
+        if blockers == 0 {
+            return attacks | ray;
+        }
+        let first = if d < 4 {
+            blockers.trailing_zeros()
+        } else {
+            127 - blockers.leading_zeros()
+        };
+        attacks | ray ^ rays[d][first as usize]
+    })
+}
+
+pub fn knight_attacks(square: usize) -> u128 {
+    tables().knight[square]
+}
+
+pub fn king_attacks(square: usize) -> u128 {
+    tables().king[square]
+}
+
+//Squares a pawn of the team strikes from the square
+pub fn pawn_attacks(team_white: bool, square: usize) -> u128 {
+    tables().pawn[team_index(team_white)][square]
+}
+
+pub fn rook_attacks(square: usize, occupied: u128) -> u128 {
+    slide(square, occupied, ROOK_DIRECTIONS)
+}
+
+pub fn bishop_attacks(square: usize, occupied: u128) -> u128 {
+    slide(square, occupied, BISHOP_DIRECTIONS)
+}
@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+
+use crate::engine::Rng;
+use crate::movegen::{castling_right, legal_moves, Move};
+use crate::pgn;
+use crate::position::Position;
+use crate::variant::Variant;
+use crate::PieceType;
+
+//Polyglot entries are 16 bytes: key, move, weight and learning data, all big-endian
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pick {
+    //Random, with more weight making a move more likely
+    Weighted,
+    Best,
+}
+
+struct Entry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+//An opening book read whole from a Polyglot .bin file, entries sorted by key
+pub struct Book {
+    entries: Vec<Entry>,
+}
+
+/*
+Moves are packed as to file, to rank, from file, from rank and promotion piece, three bits each,
+with ranks counted from white's side. Castling is written as the king taking its own rook, as
+Chess960 positions write it already.
+*/
+fn decode_move(position: &Position, data: u16) -> Option<Move> {
+    let square = |shift: u16| {
+        (
+            (data >> shift & 7) as u8,
+            7 - (data >> (shift + 3) & 7) as u8,
+        )
+    };
+    let from = square(6);
+    let mut to = square(0);
+
+    let promotion = match data >> 12 & 7 {
+        0 => None,
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => return None,
+    };
+
+    let king_castles = !position.chess960
+        && position
+            .piece_at(from)
+            .is_some_and(|x| x.piece_type == PieceType::King)
+        && from.0 == 4
+        && (to.0 == 0 || to.0 == 7)
+        && to.1 == from.1;
+    if king_castles {
+        to.0 = if to.0 == 7 { 6 } else { 2 };
+    }
+
+    legal_moves(position)
+        .into_iter()
+        .find(|x| x.from == from && x.to == to && x.promotion == promotion)
+}
+
+fn encode_move(position: &Position, mv: Move) -> u16 {
+    let to = match castling_right(position, mv) {
+        Some(right) => (position.castling_rooks[right], mv.to.1),
+        None => mv.to,
+    };
+    let square = |x: (u8, u8)| x.0 as u16 | (7 - x.1 as u16) << 3;
+
+    square(to)
+        | square(mv.from) << 6
+        | match mv.promotion {
+            Some(PieceType::Knight) => 1,
+            Some(PieceType::Bishop) => 2,
+            Some(PieceType::Rook) => 3,
+            Some(PieceType::Queen) => 4,
+            _ => 0,
+        } << 12
+}
+
+impl Book {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| format!("Could not read book {path}: {e}"))?;
+        if data.len() % ENTRY_SIZE != 0 {
+            return Err(format!("{path} is not a Polyglot book"));
+        }
+
+        let mut entries = data
+            .chunks_exact(ENTRY_SIZE)
+            .map(|x| Entry {
+                key: u64::from_be_bytes(x[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(x[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(x[10..12].try_into().unwrap()),
+            })
+            .collect::<Vec<Entry>>();
+        //Books should come sorted, but lookups depend on it so don't take it on trust
+        entries.sort_by_key(|x| x.key);
+
+        Ok(Book { entries })
+    }
+
+    //Legal book moves for the position with their weights, most weight first
+    pub fn moves(&self, position: &Position) -> Vec<(Move, u16)> {
+        //Books are for standard chess, the same position can call for other moves in a variant
+        if position.variant != Variant::Standard || (position.width, position.height) != (8, 8) {
+            return vec![];
+        }
+        let start = self.entries.partition_point(|x| x.key < position.hash);
+        let mut moves = self.entries[start..]
+            .iter()
+            .take_while(|x| x.key == position.hash)
+            .filter_map(|x| Some((decode_move(position, x.mv)?, x.weight)))
+            .collect::<Vec<(Move, u16)>>();
+
+        moves.sort_by_key(|x| std::cmp::Reverse(x.1));
+        moves
+    }
+
+    pub fn pick(&self, position: &Position, pick: Pick, rng: &mut Rng) -> Option<Move> {
+        let moves = self.moves(position);
+        let total = moves.iter().map(|x| x.1 as u64).sum::<u64>();
+
+        match pick {
+            //Moves with no weight at all are only played if nothing else is there
+            Pick::Weighted if total > 0 => {
+                let mut target = rng.next_u64() % total;
+                moves.iter().find_map(|&(mv, weight)| {
+                    if target < weight as u64 {
+                        Some(mv)
+                    } else {
+                        target -= weight as u64;
+                        None
+                    }
+                })
+            }
+            _ => moves.first().map(|x| x.0),
+        }
+    }
+}
+
+pub const BUILD_USAGE: &str = "Usage: chess build-book <pgn>... --output <file> [--plies N] \
+                               [--min-games N] [--min-score 0-100]";
+
+struct BuildOptions {
+    files: Vec<String>,
+    output: String,
+    //Only this many half moves from the start of each game make it into the book
+    plies: usize,
+    //Moves played fewer times than this are left out
+    min_games: u32,
+    //Lowest percentage the side playing a move must have scored with it
+    min_score: f64,
+}
+
+fn parse_build_args(args: &[String]) -> Result<BuildOptions, String> {
+    let mut options = BuildOptions {
+        files: vec![],
+        output: String::new(),
+        plies: 20,
+        min_games: 1,
+        min_score: 0.0,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} expects a value"));
+        match arg.as_str() {
+            "--output" => options.output = value(arg)?.clone(),
+            "--plies" => {
+                options.plies = value(arg)?
+                    .parse()
+                    .map_err(|_| "--plies expects a number")?
+            }
+            "--min-games" => {
+                options.min_games = value(arg)?
+                    .parse()
+                    .map_err(|_| "--min-games expects a number")?
+            }
+            "--min-score" => {
+                options.min_score = value(arg)?
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| (0.0..=100.0).contains(x))
+                    .ok_or("--min-score expects a percentage")?
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument {arg}")),
+            _ => options.files.push(arg.clone()),
+        }
+    }
+
+    if options.files.is_empty() || options.output.is_empty() {
+        return Err("build-book needs PGN files and an --output file".to_string());
+    }
+    Ok(options)
+}
+
+/*
+Counts how often each move was played in each position and how the side playing it did, in half
+points. Weights end up as those points, scaled down if needed to fit Polyglot's 16 bits.
+*/
+fn build(options: &BuildOptions) -> Result<Vec<Entry>, String> {
+    let mut stats = HashMap::<(u64, u16), (u32, u32)>::new();
+    let mut games = 0;
+    let mut skipped = 0;
+
+    for game in pgn::read(&options.files)? {
+        //Without a result there's nothing to score the moves by
+        let points = match game.result.as_str() {
+            "1-0" => [2, 0],
+            "0-1" => [0, 2],
+            "1/2-1/2" => [1, 1],
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        };
+        //Variant games would file their moves under standard positions
+        let Some(start) = game
+            .start()
+            .ok()
+            .filter(|x| x.variant == Variant::Standard && (x.width, x.height) == (8, 8))
+        else {
+            skipped += 1;
+            continue;
+        };
+        games += 1;
+
+        for (position, mv) in game.replay(start).take(options.plies) {
+            let entry = stats
+                .entry((position.hash, encode_move(&position, mv)))
+                .or_default();
+            entry.0 += 1;
+            entry.1 += points[if position.white_to_move { 0 } else { 1 }];
+        }
+    }
+
+    if skipped > 0 {
+        println!(
+            "Skipped {skipped} games without a result, with an invalid start position or in a variant"
+        );
+    }
+    println!("Read {games} games");
+
+    let kept = stats
+        .into_iter()
+        .filter(|(_, (count, points))| {
+            *count >= options.min_games
+                && *points as f64 * 50.0 / *count as f64 >= options.min_score
+        })
+        .collect::<Vec<((u64, u16), (u32, u32))>>();
+
+    let most = kept.iter().map(|x| x.1 .1).max().unwrap_or(0);
+    let scale = (most as f64 / u16::MAX as f64).max(1.0);
+
+    let mut entries = kept
+        .into_iter()
+        .map(|((key, mv), (_, points))| Entry {
+            key,
+            mv,
+            //Every move that passed the filters stays playable
+            weight: ((points as f64 / scale) as u16).max(1),
+        })
+        .collect::<Vec<Entry>>();
+    entries.sort_by_key(|x| (x.key, std::cmp::Reverse(x.weight)));
+
+    Ok(entries)
+}
+
+fn write(path: &str, entries: &[Entry]) -> Result<(), String> {
+    let data = entries
+        .iter()
+        .flat_map(|x| {
+            [
+                &x.key.to_be_bytes()[..],
+                &x.mv.to_be_bytes(),
+                &x.weight.to_be_bytes(),
+                //Learning data, unused
+                &[0; 4],
+            ]
+            .concat()
+        })
+        .collect::<Vec<u8>>();
+
+    std::fs::write(path, data).map_err(|e| format!("Could not write {path}: {e}"))
+}
+
+//chess build-book, returns whether it succeeded
+pub fn run_builder(args: &[String]) -> bool {
+    let result = parse_build_args(args)
+        .map_err(|e| format!("{e}\n{BUILD_USAGE}"))
+        .and_then(|options| {
+            let entries = build(&options)?;
+            write(&options.output, &entries)?;
+
+            let positions = entries
+                .iter()
+                .map(|x| x.key)
+                .collect::<std::collections::HashSet<u64>>()
+                .len();
+            println!(
+                "Wrote {} moves in {positions} positions to {}",
+                entries.len(),
+                options.output
+            );
+            Ok(())
+        });
+
+    if let Err(e) = &result {
+        println!("{e}");
+    }
+    result.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movegen::make_move;
+
+    const GAMES: &str = "[Result \"1-0\"]
+
+1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. O-O Nf6 1-0
+
+[Result \"0-1\"]
+
+1. e4 c5 0-1
+
+[Result \"1/2-1/2\"]
+
+1. d4 d5 1/2-1/2
+";
+
+    //A tiny book built from three games, written out and read back, files named for the test
+    fn tiny_book(test: &str) -> Book {
+        let dir = std::env::temp_dir();
+        let name = format!("chess-book-{test}-{}", std::process::id());
+        let pgn_path = dir
+            .join(format!("{name}.pgn"))
+            .to_string_lossy()
+            .to_string();
+        let book_path = dir
+            .join(format!("{name}.bin"))
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&pgn_path, GAMES).unwrap();
+
+        let entries = build(&BuildOptions {
+            files: vec![pgn_path.clone()],
+            output: book_path.clone(),
+            plies: 20,
+            min_games: 1,
+            min_score: 0.0,
+        })
+        .unwrap();
+        write(&book_path, &entries).unwrap();
+        let book = Book::open(&book_path).unwrap();
+
+        std::fs::remove_file(pgn_path).unwrap();
+        std::fs::remove_file(book_path).unwrap();
+        book
+    }
+
+    fn play(moves: &str) -> Position {
+        let mut position = Position::startpos();
+        for text in moves.split_whitespace() {
+            let mv = Move::from_uci(&position, text).unwrap();
+            make_move(&mut position, mv);
+        }
+        position
+    }
+
+    #[test]
+    fn moves_by_weight() {
+        let book = tiny_book("moves");
+        let moves = book
+            .moves(&Position::startpos())
+            .into_iter()
+            .map(|(mv, weight)| (mv.to_uci(), weight))
+            .collect::<Vec<(String, u16)>>();
+        assert_eq!(moves, [("e2e4".to_string(), 2), ("d2d4".to_string(), 1)]);
+        assert!(book.moves(&play("a2a3")).is_empty());
+    }
+
+    #[test]
+    fn pick() {
+        let book = tiny_book("pick");
+        let mut rng = Rng::new();
+        let start = Position::startpos();
+        assert_eq!(
+            book.pick(&start, Pick::Best, &mut rng).map(Move::to_uci),
+            Some("e2e4".to_string())
+        );
+        for _ in 0..20 {
+            let mv = book.pick(&start, Pick::Weighted, &mut rng).unwrap();
+            assert!(["e2e4", "d2d4"].contains(&mv.to_uci().as_str()));
+        }
+        assert!(book.pick(&play("a2a3"), Pick::Best, &mut rng).is_none());
+    }
+
+    //Polyglot has the king take its own rook, e1h1, which has to come back as e1g1
+    #[test]
+    fn castling() {
+        let book = tiny_book("castling");
+        let position = play("e2e4 e7e5 g1f3 b8c6 f1c4 f8c5");
+        let entry = book
+            .entries
+            .iter()
+            .find(|x| x.key == position.hash)
+            .unwrap();
+        assert_eq!(entry.mv, 4 << 6 | 7);
+
+        let moves = book.moves(&position);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].0.to_uci(), "e1g1");
+    }
+}
@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Delay {
+    None,
+    //US delay, the clock only starts running once the delay has passed
+    Simple(Duration),
+    //Time used is given back afterwards, up to the delay
+    Bronstein(Duration),
+}
+
+#[derive(Clone)]
+pub struct Period {
+    //None means the period lasts for the rest of the game
+    pub moves: Option<u32>,
+    pub time: Duration,
+    //Fischer increment added after every move
+    pub increment: Duration,
+    pub delay: Delay,
+}
+
+#[derive(Clone)]
+pub struct TimeControl {
+    //The last period repeats once the others are used up
+    pub periods: Vec<Period>,
+}
+
+#[derive(Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    //Indexed by the side, white first
+    pub remaining: [Duration; 2],
+    pub period: [usize; 2],
+    //Moves made in the current period
+    pub moves: [u32; 2],
+}
+
+fn side(team_white: bool) -> usize {
+    if team_white {
+        0
+    } else {
+        1
+    }
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|x| x.is_finite() && *x >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or(format!("Invalid number of seconds {text}"))
+}
+
+//Up to tenths of a second once it gets close
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 10 {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("00:{:02}.{}", seconds, duration.subsec_millis() / 100)
+    }
+}
+
+impl TimeControl {
+    /*
+    Periods are separated by commas, each written as [moves/]minutes[+increment][d|b delay]
+    with the increment and delay in seconds. For example 5+3 is five minutes with a three second
+    increment, 40/90+30,30+30 is the FIDE classical control, 10d5 uses a five second simple
+    delay and 10b5 a Bronstein one.
+    */
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let periods = text
+            .split(',')
+            .map(|period| {
+                let (moves, rest) = match period.split_once('/') {
+                    Some((moves, rest)) => (
+                        Some(
+                            moves
+                                .parse::<u32>()
+                                .ok()
+                                .filter(|x| *x > 0)
+                                .ok_or(format!("Invalid number of moves {moves}"))?,
+                        ),
+                        rest,
+                    ),
+                    None => (None, period),
+                };
+
+                let (rest, delay) = match rest.find(['d', 'b']) {
+                    Some(i) => {
+                        let amount = parse_seconds(&rest[i + 1..])?;
+                        (
+                            &rest[..i],
+                            if rest[i..].starts_with('d') {
+                                Delay::Simple(amount)
+                            } else {
+                                Delay::Bronstein(amount)
+                            },
+                        )
+                    }
+                    None => (rest, Delay::None),
+                };
+
+                let (minutes, increment) = rest.split_once('+').unwrap_or((rest, "0"));
+                let time = parse_seconds(minutes)
+                    .map_err(|_| format!("Invalid number of minutes {minutes}"))?
+                    * 60;
+
+                Ok(Period {
+                    moves,
+                    time,
+                    increment: parse_seconds(increment)?,
+                    delay,
+                })
+            })
+            .collect::<Result<Vec<Period>, String>>()?;
+
+        if periods
+            .iter()
+            .all(|x| x.time.is_zero() && x.increment.is_zero())
+        {
+            return Err(format!("Time control {text} gives no time at all"));
+        }
+
+        Ok(TimeControl { periods })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let periods = self
+            .periods
+            .iter()
+            .map(|period| {
+                format!(
+                    "{}{}{}{}",
+                    period.moves.map_or(String::new(), |x| format!("{x}/")),
+                    period.time.as_secs_f64() / 60.0,
+                    if period.increment.is_zero() {
+                        String::new()
+                    } else {
+                        format!("+{}", period.increment.as_secs_f64())
+                    },
+                    match period.delay {
+                        Delay::None => String::new(),
+                        Delay::Simple(v) => format!("d{}", v.as_secs_f64()),
+                        Delay::Bronstein(v) => format!("b{}", v.as_secs_f64()),
+                    }
+                )
+            })
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", periods.join(","))
+    }
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        let time = control.periods[0].time;
+        Clock {
+            control,
+            remaining: [time; 2],
+            period: [0; 2],
+            moves: [0; 2],
+        }
+    }
+
+    fn current_period(&self, team_white: bool) -> &Period {
+        let periods = &self.control.periods;
+        &periods[self.period[side(team_white)].min(periods.len() - 1)]
+    }
+
+    //Time left on the side's clock after thinking for elapsed so far
+    pub fn remaining_after(&self, team_white: bool, elapsed: Duration) -> Duration {
+        let charged = match self.current_period(team_white).delay {
+            Delay::Simple(delay) => elapsed.saturating_sub(delay),
+            _ => elapsed,
+        };
+        self.remaining[side(team_white)].saturating_sub(charged)
+    }
+
+    pub fn flagged(&self, team_white: bool, elapsed: Duration) -> bool {
+        self.remaining_after(team_white, elapsed).is_zero()
+    }
+
+    //Moves left before the next time control, None when the period runs to the end of the game
+    pub fn moves_to_go(&self, team_white: bool) -> Option<u32> {
+        self.current_period(team_white)
+            .moves
+            .map(|x| x - self.moves[side(team_white)])
+    }
+
+    //Time that comes back after each move, for planning how long to think
+    pub fn bonus(&self, team_white: bool) -> Duration {
+        let period = self.current_period(team_white);
+        period.increment
+            + match period.delay {
+                Delay::None => Duration::ZERO,
+                Delay::Simple(v) | Delay::Bronstein(v) => v,
+            }
+    }
+
+    //Charges a finished move to the side's clock, returning false if the flag fell first
+    pub fn punch(&mut self, team_white: bool, elapsed: Duration) -> bool {
+        if self.flagged(team_white, elapsed) {
+            self.remaining[side(team_white)] = Duration::ZERO;
+            return false;
+        }
+
+        let period = self.current_period(team_white).clone();
+        let i = side(team_white);
+
+        self.remaining[i] = self.remaining_after(team_white, elapsed) + period.increment;
+        if let Delay::Bronstein(delay) = period.delay {
+            self.remaining[i] += elapsed.min(delay);
+        }
+
+        self.moves[i] += 1;
+        if period.moves.is_some_and(|x| self.moves[i] >= x) {
+            self.period[i] += 1;
+            self.moves[i] = 0;
+            self.remaining[i] += self.current_period(team_white).time;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(x: u64) -> Duration {
+        Duration::from_secs(x)
+    }
+
+    fn clock(text: &str) -> Clock {
+        Clock::new(TimeControl::parse(text).unwrap())
+    }
+
+    #[test]
+    fn parse() {
+        let control = TimeControl::parse("40/90+30,30+30").unwrap();
+        assert_eq!(control.periods.len(), 2);
+        assert_eq!(control.periods[0].moves, Some(40));
+        assert_eq!(control.periods[0].time, secs(90 * 60));
+        assert_eq!(control.periods[0].increment, secs(30));
+        assert_eq!(control.periods[1].moves, None);
+        assert_eq!(control.to_string(), "40/90+30,30+30");
+
+        assert!(TimeControl::parse("10d5").unwrap().periods[0].delay == Delay::Simple(secs(5)));
+        assert!(TimeControl::parse("10b5").unwrap().periods[0].delay == Delay::Bronstein(secs(5)));
+        assert_eq!(TimeControl::parse("0.5").unwrap().periods[0].time, secs(30));
+
+        assert!(TimeControl::parse("0").is_err());
+        assert!(TimeControl::parse("0/5").is_err());
+        assert!(TimeControl::parse("five").is_err());
+        assert!(TimeControl::parse("5+x").is_err());
+    }
+
+    #[test]
+    fn increment() {
+        let mut clock = clock("5+3");
+        assert!(clock.punch(true, secs(10)));
+        assert_eq!(clock.remaining, [secs(293), secs(300)]);
+        assert_eq!(clock.bonus(true), secs(3));
+    }
+
+    #[test]
+    fn simple_delay() {
+        let mut clock = clock("5d5");
+        assert_eq!(clock.remaining_after(true, secs(4)), secs(300));
+        assert!(clock.punch(true, secs(4)));
+        assert_eq!(clock.remaining[0], secs(300));
+        assert!(clock.punch(true, secs(15)));
+        assert_eq!(clock.remaining[0], secs(290));
+    }
+
+    #[test]
+    fn bronstein_delay() {
+        let mut clock = clock("5b5");
+        assert!(clock.punch(true, secs(4)));
+        assert_eq!(clock.remaining[0], secs(300));
+        assert!(clock.punch(true, secs(15)));
+        assert_eq!(clock.remaining[0], secs(290));
+    }
+
+    #[test]
+    fn period_rollover() {
+        let mut clock = clock("2/10,5+2");
+        assert_eq!(clock.moves_to_go(true), Some(2));
+        assert!(clock.punch(true, secs(60)));
+        assert_eq!(clock.moves_to_go(true), Some(1));
+        assert!(clock.punch(true, secs(60)));
+
+        //Time left over carries into the next period, which lasts the rest of the game
+        assert_eq!(clock.period[0], 1);
+        assert_eq!(clock.remaining[0], secs(8 * 60 + 5 * 60));
+        assert_eq!(clock.moves_to_go(true), None);
+        assert_eq!(clock.bonus(true), secs(2));
+        assert_eq!(clock.moves_to_go(false), Some(2));
+    }
+
+    #[test]
+    fn flag_fall() {
+        let mut clock = clock("1");
+        assert!(!clock.flagged(false, secs(59)));
+        assert!(clock.flagged(false, secs(60)));
+        assert!(!clock.punch(false, secs(61)));
+        assert_eq!(clock.remaining[1], Duration::ZERO);
+    }
+}
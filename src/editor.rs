@@ -0,0 +1,310 @@
+use crate::bitboard::{bit, Bitboards};
+use crate::movegen::in_check;
+use crate::position::{piece_from_letter, Position};
+use crate::variant::Variant;
+use crate::{beside_board, parse_location, read_input, render_board, Piece, PieceType};
+
+const HELP: &str = "Set up the position to play from, squares as labelled on the board:
+  +Qd4          put a white queen on D4, lowercase letters for black pieces
+  -e5           take the piece off E5
+  clear         empty the board
+  start         go back to the start position
+  white, black  choose the side to move
+  castling KQkq choose castling rights, - for none
+  done          check the position and play from it";
+
+//The position set up so far, castling rights kept as typed and only checked once it's done
+struct Editor {
+    position: Position,
+    castling: String,
+}
+
+impl Editor {
+    fn new(position: Position) -> Self {
+        let castling = position
+            .to_fen()
+            .split_whitespace()
+            .nth(2)
+            .unwrap()
+            .to_string();
+        let mut editor = Editor {
+            position,
+            castling: String::new(),
+        };
+        editor.reset(&castling);
+        editor
+    }
+
+    //Rights and the en passant square belong to the position as typed, not to what came before
+    fn reset(&mut self, castling: &str) {
+        self.castling = castling.to_string();
+        self.position.castling = [false; 4];
+        self.position.en_passant = None;
+        self.position.halfmove_clock = 0;
+        self.position.fullmove_number = 1;
+    }
+
+    fn set_pieces(&mut self, pieces: Vec<Piece>) {
+        self.position.board = Bitboards::from_pieces(&pieces);
+        self.position.pieces = pieces;
+    }
+
+    //Pieces come off before another goes on the same square
+    fn remove(&mut self, square: (u8, u8)) -> bool {
+        let mut pieces = self.position.pieces.clone();
+        pieces.retain(|x| x.position != square);
+        let removed = pieces.len() != self.position.pieces.len();
+        self.position.promoted &= !bit(square);
+        self.set_pieces(pieces);
+        removed
+    }
+
+    fn place(&mut self, piece: Piece) {
+        self.remove(piece.position);
+        let mut pieces = self.position.pieces.clone();
+        pieces.push(piece);
+        self.set_pieces(pieces);
+    }
+
+    //The position as FEN would have it, which is where castling rights get checked
+    fn build(&self) -> Result<Position, String> {
+        let fen = self.position.to_fen();
+        let mut fields = fen.split_whitespace().collect::<Vec<&str>>();
+        fields[2] = &self.castling;
+        let mut position = Position::from_fen(&fields.join(" "))?;
+        position.variant = self.position.variant;
+        position.chess960 |= self.position.chess960;
+        Ok(position)
+    }
+
+    //Where a label like D4 is on this board, None if it's off it
+    fn square(&self, label: &str) -> Option<(u8, u8)> {
+        let label = label.to_uppercase();
+        let chars = label.as_bytes();
+        let position = &self.position;
+        let on_board = chars.len() == 2
+            && (b'A'..b'A' + position.width).contains(&chars[0])
+            && (b'1'..=b'0' + position.height).contains(&chars[1]);
+        on_board.then(|| parse_location(&label, position.top()))
+    }
+
+    fn panel(&self) -> Vec<(usize, String)> {
+        vec![
+            (
+                1,
+                format!(
+                    "   {} to move",
+                    if self.position.white_to_move {
+                        "White"
+                    } else {
+                        "Black"
+                    }
+                ),
+            ),
+            (2, format!("   Castling: {}", self.castling)),
+        ]
+    }
+}
+
+/*
+Whether the position could come up in a game: one king a side, no pawns on the first or last row
+and no capturing the king straight away. Horde's white side has no king and its pawns may stand on
+the first row, antichess kings are ordinary pieces.
+*/
+fn validate(position: &Position) -> Result<(), String> {
+    let variant = position.variant;
+    for team_white in [true, false] {
+        let kings = position
+            .pieces
+            .iter()
+            .filter(|x| x.team_white == team_white && x.piece_type == PieceType::King)
+            .count();
+        let needed = match variant {
+            Variant::Antichess => continue,
+            Variant::Horde if team_white => 0,
+            _ => 1,
+        };
+        if kings != needed {
+            return Err(format!(
+                "{} needs {} king, not {kings}",
+                if team_white { "White" } else { "Black" },
+                if needed == 0 { "no" } else { "exactly one" }
+            ));
+        }
+    }
+
+    let back_rank = position.pieces.iter().find(|x| {
+        x.piece_type == PieceType::Pawn
+            && (x.position.1 == position.top()
+                || (x.position.1 == 7 && !(variant == Variant::Horde && x.team_white)))
+    });
+    if back_rank.is_some() {
+        return Err("Pawns can't stand on the first or last row".to_string());
+    }
+
+    let mut passed = position.clone();
+    passed.white_to_move = !passed.white_to_move;
+    if variant.royal_king() && in_check(&passed) {
+        return Err(format!(
+            "{} is in check but it's not their move",
+            if position.white_to_move {
+                "Black"
+            } else {
+                "White"
+            }
+        ));
+    }
+    Ok(())
+}
+
+//Edits the position until it's one that can be played from, which is returned
+pub fn run(start: Position) -> Position {
+    let mut editor = Editor::new(start);
+    println!("{HELP}");
+
+    loop {
+        println!(
+            "{}",
+            beside_board(&render_board(&editor.position), &editor.panel())
+        );
+
+        let input = read_input();
+        let words = input.split_whitespace().collect::<Vec<&str>>();
+        let Some(&command) = words.first() else {
+            continue;
+        };
+
+        match command.to_lowercase().as_str() {
+            "clear" => {
+                editor.set_pieces(vec![]);
+                editor.reset("-");
+            }
+            "start" => match editor.position.variant.start() {
+                Ok(v) => editor = Editor::new(v),
+                Err(e) => println!("{e}"),
+            },
+            "white" | "black" => {
+                editor.position.white_to_move = command.eq_ignore_ascii_case("white")
+            }
+            "castling" => match words.get(1) {
+                Some(v) => editor.castling = v.to_string(),
+                None => println!("Castling expects rights like KQkq, or - for none"),
+            },
+            "done" => match editor.build().and_then(|x| validate(&x).map(|_| x)) {
+                Ok(v) => return v,
+                Err(e) => println!("{e}"),
+            },
+            "help" => println!("{HELP}"),
+            _ if command.starts_with('+') => {
+                let mut chars = command[1..].chars();
+                let piece = chars.next().and_then(|x| Some((x, piece_from_letter(x)?)));
+                match (piece, editor.square(chars.as_str())) {
+                    (Some((letter, piece_type)), Some(square)) => editor.place(Piece {
+                        team_white: letter.is_ascii_uppercase(),
+                        piece_type,
+                        position: square,
+                    }),
+                    (None, _) => println!("Unknown piece in {command}"),
+                    (_, None) => println!("No square {} on this board", chars.as_str()),
+                }
+            }
+            _ if command.starts_with('-') => match editor.square(&command[1..]) {
+                Some(square) => {
+                    if !editor.remove(square) {
+                        println!("No piece on {}", command[1..].to_uppercase());
+                    }
+                }
+                None => println!("No square {} on this board", &command[1..]),
+            },
+            _ => println!("Unknown command {command}, type help for the list"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(fen: &str, variant: Variant) -> Result<(), String> {
+        let mut position = Position::from_fen(fen).unwrap();
+        position.variant = variant;
+        validate(&position)
+    }
+
+    #[test]
+    fn kings() {
+        assert!(check("4k3/8/8/8/8/8/8/4K3 w - - 0 1", Variant::Standard).is_ok());
+        assert_eq!(
+            check("4k3/8/8/8/8/8/8/8 w - - 0 1", Variant::Standard),
+            Err("White needs exactly one king, not 0".to_string())
+        );
+        assert_eq!(
+            check("3kk3/8/8/8/8/8/8/4K3 w - - 0 1", Variant::Standard),
+            Err("Black needs exactly one king, not 2".to_string())
+        );
+        assert!(check("8/8/8/8/8/8/8/2K1K3 w - - 0 1", Variant::Antichess).is_ok());
+        assert!(check("4k3/8/8/8/8/8/8/4P3 w - - 0 1", Variant::Horde).is_ok());
+        assert_eq!(
+            check("4k3/8/8/8/8/8/8/4K3 w - - 0 1", Variant::Horde),
+            Err("White needs no king, not 1".to_string())
+        );
+    }
+
+    #[test]
+    fn pawns() {
+        let error = Err("Pawns can't stand on the first or last row".to_string());
+        assert_eq!(
+            check("3Pk3/8/8/8/8/8/8/4K3 w - - 0 1", Variant::Standard),
+            error
+        );
+        assert_eq!(
+            check("4k3/8/8/8/8/8/8/3pK3 w - - 0 1", Variant::Standard),
+            error
+        );
+        //Only horde's white pawns may start on the first row
+        assert!(check("4k3/8/8/8/8/8/8/3P4 w - - 0 1", Variant::Horde).is_ok());
+        assert_eq!(
+            check("3Pk3/8/8/8/8/8/8/4P3 w - - 0 1", Variant::Horde),
+            error
+        );
+    }
+
+    #[test]
+    fn check_on_the_wrong_side() {
+        assert_eq!(
+            check("4k3/8/8/8/8/8/8/4KR2 w - - 0 1", Variant::Standard),
+            Ok(())
+        );
+        assert_eq!(
+            check("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", Variant::Standard),
+            Err("Black is in check but it's not their move".to_string())
+        );
+        //Antichess kings can be taken like anything else
+        assert!(check("4k3/8/8/8/8/8/8/4R1K1 w - - 0 1", Variant::Antichess).is_ok());
+    }
+
+    #[test]
+    fn editing() {
+        let mut editor = Editor::new(Position::startpos());
+        assert_eq!(editor.castling, "KQkq");
+        assert!(editor.square("I1").is_none());
+        assert!(editor.square("A9").is_none());
+
+        //Labels count down from the top, so D1 is black's queen
+        let d1 = editor.square("d1").unwrap();
+        assert!(editor.remove(d1));
+        assert!(!editor.remove(d1));
+        editor.place(Piece {
+            team_white: true,
+            piece_type: PieceType::Knight,
+            position: d1,
+        });
+        editor.castling = "Kk".to_string();
+        let position = editor.build().unwrap();
+        assert_eq!(
+            position.to_fen(),
+            "rnbNkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kk - 0 1"
+        );
+        assert!(validate(&position).is_ok());
+    }
+}
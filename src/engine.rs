@@ -0,0 +1,612 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::fairy;
+use crate::movegen::{legal_moves, make_move, unmake_move, Move, Outcome};
+use crate::position::Position;
+use crate::variant::{self, Variant};
+use crate::PieceType;
+
+pub const MATE_SCORE: i32 = 100_000;
+const INFINITY: i32 = 1_000_000;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 10;
+pub const MAX_DEPTH: u8 = 64;
+
+//How long the engine thinks in the game loop when nothing else limits it
+pub const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(5);
+//Moves left in the game assumed when the time control doesn't say
+pub const DEFAULT_MOVES_TO_GO: u32 = 30;
+//Kept in reserve so the engine never loses on time to its own overhead
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+/*
+Piece square tables from white's point of view, the first row being the far side of the board
+(row 0 in piece positions). Black reads them mirrored.
+*/
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    //Centipawns from white's point of view
+    pub score: i32,
+    pub depth: u8,
+    pub nodes: u64,
+}
+
+//Progress reported after every finished depth
+pub struct SearchInfo {
+    pub depth: u8,
+    //Centipawns from the side to move's point of view, as engine protocols expect
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<Move>,
+}
+
+//Limits for a single search on top of the level's own, None meaning no limit
+#[derive(Default)]
+pub struct Limits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    //Root moves to choose between, as UCI's searchmoves
+    pub moves: Option<Vec<Move>>,
+}
+
+//How the engine holds back at a given level
+pub struct Difficulty {
+    max_depth: u8,
+    max_nodes: u64,
+    //Moves scoring within this many centipawns of the best one may be picked instead
+    random_margin: i32,
+    //Chance of overlooking a capture or a long move somewhere in the search tree
+    blunder_chance: f64,
+}
+
+impl Difficulty {
+    pub fn from_level(level: u8) -> Self {
+        let (max_depth, max_nodes, random_margin, blunder_chance) =
+            match level.clamp(MIN_LEVEL, MAX_LEVEL) {
+                1 => (1, 2_000, 150, 0.5),
+                2 => (1, 4_000, 100, 0.4),
+                3 => (2, 8_000, 80, 0.3),
+                4 => (2, 15_000, 60, 0.2),
+                5 => (2, 30_000, 40, 0.15),
+                6 => (3, 60_000, 25, 0.1),
+                7 => (3, 100_000, 15, 0.05),
+                8 => (3, 150_000, 8, 0.02),
+                9 => (4, 200_000, 0, 0.0),
+                //Full strength, only the search limits hold it back
+                _ => (MAX_DEPTH, u64::MAX, 0, 0.0),
+            };
+
+        Difficulty {
+            max_depth,
+            max_nodes,
+            random_margin,
+            blunder_chance,
+        }
+    }
+}
+
+//Xorshift generator, good enough for picking moves without pulling in a crate
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos() as u64);
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    //Uniform in 0..1
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn piece_value(piece_type: &PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 20_000,
+        PieceType::Fairy(i) => fairy::get(*i).value,
+    }
+}
+
+//Material and piece placement in centipawns, positive is good for white
+pub fn evaluate(position: &Position) -> i32 {
+    position
+        .pieces
+        .iter()
+        .map(|piece| {
+            //Black's rows are mirrored within the board, the tables only go eight files wide
+            let row = if piece.team_white {
+                piece.position.1
+            } else {
+                position.top() + 7 - piece.position.1
+            };
+            let square = row as usize * 8 + piece.position.0.min(7) as usize;
+
+            let table = match piece.piece_type {
+                PieceType::Pawn => &PAWN_TABLE,
+                PieceType::Knight => &KNIGHT_TABLE,
+                PieceType::Bishop => &BISHOP_TABLE,
+                PieceType::Rook => &ROOK_TABLE,
+                PieceType::Queen => &QUEEN_TABLE,
+                PieceType::King => &KING_TABLE,
+                PieceType::Fairy(_) => &[0; 64],
+            };
+
+            let value = piece_value(&piece.piece_type) + table[square];
+            if piece.team_white {
+                value
+            } else {
+                -value
+            }
+        })
+        .sum()
+}
+
+/*
+Games won outside checkmate score like mates, from the side to move's point of view. Nearer wins
+score higher.
+*/
+fn variant_result(position: &Position, ply: i32) -> Option<i32> {
+    let outcome = variant::outcome(position)?;
+    Some(match outcome {
+        Outcome::Draw(_) => 0,
+        Outcome::WhiteWins(_) if position.white_to_move => MATE_SCORE - ply,
+        Outcome::BlackWins(_) if !position.white_to_move => MATE_SCORE - ply,
+        _ => -MATE_SCORE + ply,
+    })
+}
+
+/*
+The usual evaluation plus what brings each variant's own win closer. Antichess turns material
+around, as every piece lost is a step towards winning.
+*/
+fn evaluate_position(position: &Position) -> i32 {
+    let kings = |bonus: &dyn Fn((u8, u8)) -> i32| {
+        position
+            .pieces
+            .iter()
+            .filter(|x| x.piece_type == PieceType::King)
+            .map(|x| if x.team_white { 1 } else { -1 } * bonus(x.position))
+            .sum::<i32>()
+    };
+
+    match position.variant {
+        Variant::Standard
+        | Variant::Atomic
+        | Variant::Capablanca
+        | Variant::Gardner
+        | Variant::LosAlamos => evaluate(position),
+        //White has no king to count, so neither side's is
+        Variant::Horde => evaluate(position) - kings(&|_| piece_value(&PieceType::King)),
+        Variant::KingOfTheHill => {
+            evaluate(position)
+                + kings(&|(x, y)| {
+                    //Rings around the centre, 0 on the hill itself
+                    let ring = (2 * x as i32 - 7).abs().max((2 * y as i32 - 7).abs()) / 2;
+                    (3 - ring) * 50
+                })
+        }
+        Variant::ThreeCheck => {
+            evaluate(position) + (position.checks[0] as i32 - position.checks[1] as i32) * 250
+        }
+        //Both kings race for the row at black's side of the board
+        Variant::RacingKings => evaluate(position) + kings(&|(_, y)| (7 - y as i32) * 60),
+        //Pieces in hand can go anywhere, so they count for as much as those on the board
+        Variant::Crazyhouse => {
+            evaluate(position)
+                + (0..5)
+                    .map(|i| {
+                        (position.pockets[0][i] as i32 - position.pockets[1][i] as i32)
+                            * piece_value(&variant::POCKET[i])
+                    })
+                    .sum::<i32>()
+        }
+        Variant::Antichess => -position
+            .pieces
+            .iter()
+            .map(|x| {
+                let value = match x.piece_type {
+                    PieceType::King => 300,
+                    v => piece_value(&v),
+                };
+                if x.team_white {
+                    value
+                } else {
+                    -value
+                }
+            })
+            .sum::<i32>(),
+    }
+}
+
+//Most valuable victim first, cheapest attacker breaking ties
+fn order_moves(position: &Position, moves: &mut [Move]) {
+    let victim_value = |mv: &Move| {
+        variant::captured(position, *mv).map_or(0, |x| piece_value(&x) * 10)
+            + mv.promotion.map_or(0, |x| piece_value(&x))
+            - position
+                .piece_at(mv.from)
+                .map_or(0, |x| piece_value(&x.piece_type) / 100)
+    };
+    moves.sort_by_key(|mv| -victim_value(mv));
+}
+
+struct Search<'a> {
+    difficulty: &'a Difficulty,
+    //Decides which moves get overlooked, fixed for one search so every line agrees
+    blind_seed: u64,
+    max_nodes: u64,
+    deadline: Option<Instant>,
+    stop: &'a AtomicBool,
+    nodes: u64,
+    aborted: bool,
+}
+
+impl Search<'_> {
+    //Humans miss captures and long moves far more often than short quiet ones
+    fn overlooks(&self, position: &Position, mv: Move, ply: i32) -> bool {
+        if ply == 0 || self.difficulty.blunder_chance == 0.0 {
+            return false;
+        }
+
+        let capture = variant::is_capture(position, mv);
+        let long = (mv.to.0 as i8 - mv.from.0 as i8)
+            .abs()
+            .max((mv.to.1 as i8 - mv.from.1 as i8).abs())
+            >= 3;
+        if !capture && !long {
+            return false;
+        }
+
+        let mut hash = Rng(self.blind_seed
+            ^ ((mv.from.0 as u64) << 8
+                | (mv.from.1 as u64) << 16
+                | (mv.to.0 as u64) << 24
+                | (mv.to.1 as u64) << 32
+                | (ply as u64 % 2) << 40));
+        hash.next_u64();
+        hash.next_f64() < self.difficulty.blunder_chance
+    }
+
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.nodes >= self.max_nodes
+            || self.stop.load(Ordering::Relaxed)
+            || self.deadline.is_some_and(|x| Instant::now() >= x)
+        {
+            self.aborted = true;
+        }
+    }
+
+    fn quiescence(&mut self, position: &mut Position, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        self.count_node();
+
+        if let Some(score) = variant_result(position, ply) {
+            return score;
+        }
+        let stand_pat = evaluate_position(position) * if position.white_to_move { 1 } else { -1 };
+        if stand_pat >= beta || self.aborted {
+            return stand_pat.min(beta);
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut captures = legal_moves(position)
+            .into_iter()
+            .filter(|&mv| variant::is_capture(position, mv) && !self.overlooks(position, mv, ply))
+            .collect::<Vec<Move>>();
+        order_moves(position, &mut captures);
+
+        for mv in captures {
+            let undo = make_move(position, mv);
+            let score = -self.quiescence(position, ply + 1, -beta, -alpha);
+            unmake_move(position, mv, undo);
+            if score >= beta {
+                return beta;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    //Negamax with alpha-beta pruning, scores are from the side to move's point of view
+    fn alpha_beta(
+        &mut self,
+        position: &mut Position,
+        depth: u8,
+        ply: i32,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        if let Some(score) = variant_result(position, ply) {
+            return score;
+        }
+        let mut moves = legal_moves(position);
+
+        if moves.is_empty() {
+            //Prefer the quickest mate
+            return match variant::no_moves(position) {
+                Outcome::Draw(_) => 0,
+                Outcome::WhiteWins(_) if position.white_to_move => MATE_SCORE - ply,
+                Outcome::BlackWins(_) if !position.white_to_move => MATE_SCORE - ply,
+                _ => -MATE_SCORE + ply,
+            };
+        }
+
+        //Fifty moves without a capture or pawn move is a draw
+        if position.halfmove_clock >= 100 {
+            return 0;
+        }
+
+        if depth == 0 {
+            return self.quiescence(position, ply, alpha, beta);
+        }
+
+        self.count_node();
+        if self.aborted {
+            return alpha;
+        }
+
+        moves.retain(|&mv| !self.overlooks(position, mv, ply));
+        order_moves(position, &mut moves);
+
+        let mut line = vec![];
+        for mv in moves {
+            let undo = make_move(position, mv);
+            let score = -self.alpha_beta(position, depth - 1, ply + 1, -beta, -alpha, &mut line);
+            unmake_move(position, mv, undo);
+            if score >= beta {
+                return beta;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(mv);
+                pv.extend(line.iter().copied());
+            }
+        }
+
+        alpha
+    }
+
+    /*
+    Search every root move, keeping exact scores for the ones within the random margin of the best.
+    Returns None if the search was cut short before finishing.
+    */
+    fn root(
+        &mut self,
+        position: &mut Position,
+        moves: &[Move],
+        depth: u8,
+    ) -> Option<Vec<(Move, i32, Vec<Move>)>> {
+        let mut scored = vec![];
+        let mut best = -INFINITY;
+
+        for &mv in moves {
+            let alpha = if best == -INFINITY {
+                -INFINITY
+            } else {
+                best - self.difficulty.random_margin - 1
+            };
+
+            let mut line = vec![];
+            let undo = make_move(position, mv);
+            let score = -self.alpha_beta(position, depth - 1, 1, -INFINITY, -alpha, &mut line);
+            unmake_move(position, mv, undo);
+            if self.aborted {
+                return None;
+            }
+
+            best = best.max(score);
+            scored.push((mv, score, [vec![mv], line].concat()));
+        }
+
+        Some(scored)
+    }
+}
+
+//Spread what's left on the clock over the moves still to play, plus most of the increment
+pub fn time_for_move(remaining: Duration, increment: Duration, moves_to_go: u32) -> Duration {
+    (remaining / moves_to_go.max(1) + increment * 3 / 4)
+        .min(remaining.saturating_sub(MOVE_OVERHEAD))
+        .max(Duration::from_millis(10))
+}
+
+//Iterative deepening until the level or the limits call it off
+pub fn search(
+    position: &Position,
+    difficulty: &Difficulty,
+    limits: &Limits,
+    stop: &AtomicBool,
+    rng: &mut Rng,
+    report: &mut dyn FnMut(&SearchInfo),
+) -> SearchResult {
+    let start = Instant::now();
+
+    let mut moves = legal_moves(position);
+    if let Some(only) = &limits.moves {
+        moves.retain(|x| only.contains(x));
+    }
+    order_moves(position, &mut moves);
+
+    let mut search = Search {
+        difficulty,
+        blind_seed: rng.next_u64(),
+        max_nodes: difficulty.max_nodes.min(limits.nodes.unwrap_or(u64::MAX)),
+        deadline: limits.time.map(|x| start + x),
+        stop,
+        nodes: 0,
+        aborted: false,
+    };
+
+    let max_depth = difficulty
+        .max_depth
+        .min(limits.depth.unwrap_or(MAX_DEPTH))
+        .max(1);
+
+    //Searched in place, every move is taken back before the next one is tried
+    let mut position = position.clone();
+    let mut completed = None;
+    for depth in 1..=max_depth {
+        //Nothing to search when there are no moves, or none of those asked for
+        let Some(mut scored) = search
+            .root(&mut position, &moves, depth)
+            .filter(|x| !x.is_empty())
+        else {
+            break;
+        };
+
+        scored.sort_by_key(|x| -x.1);
+        //Try the previous best moves first next time around
+        moves = scored.iter().map(|x| x.0).collect();
+
+        report(&SearchInfo {
+            depth,
+            score: scored[0].1,
+            nodes: search.nodes,
+            time: start.elapsed(),
+            pv: scored[0].2.clone(),
+        });
+
+        let mate_found = scored[0].1.abs() > MATE_SCORE - 1000;
+        completed = Some((scored, depth));
+
+        //The next depth would take several times longer than this one did
+        if mate_found || limits.time.is_some_and(|x| start.elapsed() * 2 >= x) {
+            break;
+        }
+    }
+
+    let Some((scored, depth)) = completed else {
+        return SearchResult {
+            best_move: moves.first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: search.nodes,
+        };
+    };
+
+    let best = scored[0].1;
+    let candidates = scored
+        .iter()
+        .filter(|x| x.1 >= best - difficulty.random_margin)
+        .collect::<Vec<_>>();
+    //Without a margin stick to the line that was reported, even if others tie with it
+    let (best_move, score, _) = if difficulty.random_margin == 0 {
+        &scored[0]
+    } else {
+        candidates[rng.next_u64() as usize % candidates.len()]
+    };
+
+    SearchResult {
+        best_move: Some(*best_move),
+        score: if position.white_to_move {
+            *score
+        } else {
+            -score
+        },
+        depth,
+        nodes: search.nodes,
+    }
+}
+
+//Pawn units, or moves until mate once one is found
+pub fn format_score(score: i32) -> String {
+    if score.abs() > MATE_SCORE - 1000 {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        format!("{}M{moves}", if score > 0 { "+" } else { "-" })
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::OnceLock;
+
+use crate::bitboard::FILES;
+use crate::position::piece_from_letter;
+
+//At most this many pieces beyond the usual six, each getting a bitboard of its own
+pub const MAX_PIECES: usize = 8;
+
+//A piece beyond the usual six, described by the ways it moves
+pub struct FairyPiece {
+    pub name: String,
+    //Written in FEN as the uppercase letter for white and the lowercase one for black
+    pub letter: char,
+    //White's glyph then black's, for the board
+    pub glyphs: (char, char),
+    pub value: i32,
+    //Single jumps to a square, like a knight
+    pub leaps: Vec<(i8, i8)>,
+    //Slides along a line until something is in the way, like a rook
+    pub rides: Vec<(i8, i8)>,
+    //Slides up to the first piece on a line and lands just beyond it, like a grasshopper
+    pub hops: Vec<(i8, i8)>,
+}
+
+//What a pieces file holds: the pieces, and the position to start from if it gives one
+pub struct Definitions {
+    pub pieces: Vec<FairyPiece>,
+    pub start: Option<String>,
+}
+
+static PIECES: OnceLock<Vec<FairyPiece>> = OnceLock::new();
+//Where the pieces came from, unset while there are none or they're Capablanca's own
+static FILE: OnceLock<String> = OnceLock::new();
+
+//Empty until a pieces file is loaded
+pub fn pieces() -> &'static [FairyPiece] {
+    PIECES.get().map_or(&[], |x| x.as_slice())
+}
+
+pub fn file() -> Option<&'static str> {
+    FILE.get().map(String::as_str)
+}
+
+pub fn from_file() -> bool {
+    file().is_some()
+}
+
+pub fn get(index: u8) -> &'static FairyPiece {
+    &pieces()[index as usize]
+}
+
+//Every way of turning and mirroring an offset, so pieces move alike in all directions
+fn symmetric(offsets: &[(i8, i8)]) -> Vec<(i8, i8)> {
+    let mut all = vec![];
+    for &(x, y) in offsets {
+        for (a, b) in [(x, y), (y, x)] {
+            for (a, b) in [(a, b), (-a, b), (a, -b), (-a, -b)] {
+                if !all.contains(&(a, b)) {
+                    all.push((a, b));
+                }
+            }
+        }
+    }
+    all
+}
+
+fn parse_offset(text: &str) -> Option<(i8, i8)> {
+    let (x, y) = text.split_once(',')?;
+    let offset = (x.trim().parse::<i8>().ok()?, y.trim().parse::<i8>().ok()?);
+    (offset != (0, 0) && offset.0.abs() < 8 && offset.1.abs() < 8).then_some(offset)
+}
+
+/*
+One setting per line, each piece starting with its name and letter:
+
+    piece archbishop a
+    glyphs A a
+    value 825
+    leap 1,2
+    ride 1,1
+
+Offsets are turned and mirrored, so leap 1,2 is every knight move. A start line with a FEN sets
+the position to play from. Empty lines and lines starting with # are skipped.
+*/
+pub fn parse(text: &str) -> Result<Definitions, String> {
+    let mut pieces: Vec<FairyPiece> = vec![];
+    let mut start = None;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+        let error = |message: &str| format!("Line {}: {message}", number + 1);
+
+        if key == "start" {
+            start = Some(value.to_string());
+            continue;
+        }
+        if key == "piece" {
+            let (name, letter) = value
+                .rsplit_once(' ')
+                .ok_or(error("piece expects a name and a letter"))?;
+            let letter = match letter.chars().collect::<Vec<char>>()[..] {
+                [v] if v.is_ascii_alphabetic() => v.to_ascii_lowercase(),
+                _ => return Err(error("A piece's letter must be a single letter")),
+            };
+            if piece_from_letter(letter).is_some() || pieces.iter().any(|x| x.letter == letter) {
+                return Err(error(&format!("The letter {letter} is already taken")));
+            }
+            if pieces.len() == MAX_PIECES {
+                return Err(error(&format!("At most {MAX_PIECES} pieces can be added")));
+            }
+
+            pieces.push(FairyPiece {
+                name: name.trim().to_string(),
+                letter,
+                glyphs: (letter.to_ascii_uppercase(), letter),
+                value: 300,
+                leaps: vec![],
+                rides: vec![],
+                hops: vec![],
+            });
+            continue;
+        }
+
+        let piece = pieces
+            .last_mut()
+            .ok_or(error(&format!("{key} comes before any piece")))?;
+        let offset = || parse_offset(value).ok_or(error(&format!("Invalid offset {value}")));
+        match key {
+            "leap" => piece.leaps.push(offset()?),
+            "ride" => piece.rides.push(offset()?),
+            "hop" => piece.hops.push(offset()?),
+            "value" => {
+                piece.value = value
+                    .parse::<i32>()
+                    .map_err(|_| error(&format!("Invalid value {value}")))?
+            }
+            "glyphs" => {
+                piece.glyphs = match value.chars().filter(|x| *x != ' ').collect::<Vec<char>>()[..]
+                {
+                    [white, black] => (white, black),
+                    _ => return Err(error("glyphs expects one character for each side")),
+                }
+            }
+            _ => return Err(error(&format!("Unknown setting {key}"))),
+        }
+    }
+
+    for piece in &mut pieces {
+        if piece.leaps.is_empty() && piece.rides.is_empty() && piece.hops.is_empty() {
+            return Err(format!("The {} has no moves", piece.name));
+        }
+        piece.leaps = symmetric(&piece.leaps);
+        piece.rides = symmetric(&piece.rides);
+        piece.hops = symmetric(&piece.hops);
+    }
+
+    Ok(Definitions { pieces, start })
+}
+
+//Capablanca's archbishop and chancellor, for when no pieces file gives them
+const CAPABLANCA: &str = "
+piece archbishop a
+value 825
+leap 1,2
+ride 1,1
+
+piece chancellor c
+value 875
+leap 1,2
+ride 1,0
+";
+
+//Capablanca chess brings its own pieces, unless a pieces file was loaded first
+pub fn load_capablanca() {
+    PIECES.get_or_init(|| parse(CAPABLANCA).unwrap().pieces);
+}
+
+//Pieces can only be loaded once, before any position uses them
+pub fn load(path: &str) -> Result<Option<String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    let definitions = parse(&text)?;
+    PIECES
+        .set(definitions.pieces)
+        .map_err(|_| "Pieces are already loaded".to_string())?;
+    FILE.get_or_init(|| path.to_string());
+    Ok(definitions.start)
+}
+
+//Squares the piece attacks from the square, counted from a1 like bitboards
+pub fn attacks(index: u8, square: usize, occupied: u128) -> u128 {
+    let piece = get(index);
+    let (file, rank) = ((square % FILES) as i8, (square / FILES) as i8);
+    let on_board = |x: i8, y: i8| (0..FILES as i8).contains(&x) && (0..8).contains(&y);
+    let bit = |x: i8, y: i8| 1u128 << (y as usize * FILES + x as usize);
+    let mut attacks = 0;
+
+    for &(x, y) in &piece.leaps {
+        if on_board(file + x, rank + y) {
+            attacks |= bit(file + x, rank + y);
+        }
+    }
+
+    for &(x, y) in &piece.rides {
+        let (mut f, mut r) = (file + x, rank + y);
+        while on_board(f, r) {
+            attacks |= bit(f, r);
+            if occupied & bit(f, r) != 0 {
+                break;
+            }
+            f += x;
+            r += y;
+        }
+    }
+
+    //Over the first piece in the way, if there's board beyond it
+    for &(x, y) in &piece.hops {
+        let (mut f, mut r) = (file + x, rank + y);
+        while on_board(f, r) {
+            if occupied & bit(f, r) != 0 {
+                if on_board(f + x, r + y) {
+                    attacks |= bit(f + x, r + y);
+                }
+                break;
+            }
+            f += x;
+            r += y;
+        }
+    }
+
+    attacks
+}
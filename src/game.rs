@@ -0,0 +1,315 @@
+use std::time::Duration;
+
+use crate::clock::{Clock, TimeControl};
+use crate::fairy;
+use crate::movegen::{make_move, Move};
+use crate::position::Position;
+use crate::variant::Variant;
+use crate::{parse_level, parse_player, player_name, Piece, Settings};
+
+/*
+First line of every save file, followed by the format version. The version goes up with each key
+that changes the rules the moves are replayed under, as older builds skip keys they don't know.
+2 added chess960, variant and pieces.
+*/
+const SAVE_HEADER: &str = "chess save";
+const SAVE_VERSION: u32 = 2;
+
+//A game being played, kept together so it can be written to disk and picked up again
+pub struct Game {
+    pub start: Position,
+    pub moves: Vec<Move>,
+    pub position: Position,
+    pub fallen_pieces: Vec<Piece>,
+    pub clock: Option<Clock>,
+}
+
+impl Game {
+    pub fn new(start: Position, time_control: Option<TimeControl>) -> Self {
+        Game {
+            position: start.clone(),
+            start,
+            moves: vec![],
+            fallen_pieces: vec![],
+            clock: time_control.map(Clock::new),
+        }
+    }
+
+    pub fn play(&mut self, mv: Move) {
+        let undo = make_move(&mut self.position, mv);
+        self.fallen_pieces.extend(undo.captured);
+        //Atomic explosions take the capturing piece and its neighbours along
+        self.fallen_pieces.extend(undo.exploded);
+        self.moves.push(mv);
+    }
+
+    /*
+    One setting per line, moves in UCI notation. The side to move is charged for the time it
+    has already spent so reloading doesn't hand out free thinking time.
+    */
+    pub fn to_save(&self, settings: &Settings, elapsed: Duration) -> String {
+        let mut lines = vec![
+            format!("{SAVE_HEADER} {SAVE_VERSION}"),
+            format!("start {}", self.start.to_fen()),
+        ];
+        //The usual setup can also be a Chess960 one, which castles differently
+        if self.start.chess960 {
+            lines.push("chess960".to_string());
+        }
+        if self.start.variant != Variant::Standard {
+            lines.push(format!("variant {}", self.start.variant.name()));
+        }
+        //Kept as given, so resuming works from where the game was started
+        if let Some(path) = fairy::file() {
+            lines.push(format!("pieces {path}"));
+        }
+        lines.extend([
+            format!("white {}", player_name(settings.white)),
+            format!("black {}", player_name(settings.black)),
+            format!("level {}", settings.level),
+        ]);
+
+        if let Some(v) = &settings.uci_engine {
+            lines.push(format!("uci-engine {v}"));
+        }
+        if let Some(clock) = &self.clock {
+            let white_to_move = self.position.white_to_move;
+            let remaining = |team_white: bool| {
+                if team_white == white_to_move {
+                    clock.remaining_after(team_white, elapsed)
+                } else {
+                    clock.remaining_after(team_white, Duration::ZERO)
+                }
+            };
+
+            lines.push(format!("time-control {}", clock.control));
+            lines.push(format!(
+                "clock {} {} {} {} {} {}",
+                remaining(true).as_millis(),
+                remaining(false).as_millis(),
+                clock.period[0],
+                clock.period[1],
+                clock.moves[0],
+                clock.moves[1]
+            ));
+        }
+
+        lines.push(format!(
+            "moves {}",
+            self.moves
+                .iter()
+                .map(|x| x.to_uci())
+                .collect::<Vec<String>>()
+                .join(" ")
+        ));
+
+        lines.join("\n") + "\n"
+    }
+
+    //Player settings in the save replace the current ones
+    pub fn from_save(text: &str, settings: &mut Settings) -> Result<Self, String> {
+        let mut lines = text.lines();
+
+        let version = lines
+            .next()
+            .and_then(|x| x.strip_prefix(SAVE_HEADER))
+            .and_then(|x| x.trim().parse::<u32>().ok())
+            .ok_or("Not a saved game")?;
+        if version > SAVE_VERSION {
+            return Err(format!(
+                "Saved game is version {version}, newer than the supported {SAVE_VERSION}"
+            ));
+        }
+
+        let mut start_fen = None;
+        let mut chess960 = false;
+        let mut variant = Variant::Standard;
+        let mut pieces = None;
+        let mut time_control = None;
+        let mut clock_state = None;
+        let mut moves = vec![];
+        let mut loaded = Settings {
+            uci_engine: None,
+            time_control: None,
+            ..settings.clone()
+        };
+
+        for line in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "start" => start_fen = Some(value),
+                "chess960" => chess960 = true,
+                "variant" => {
+                    variant = Variant::parse(value)
+                        .ok_or(format!("Unknown variant {value} in saved game"))?
+                }
+                "pieces" => pieces = Some(value),
+                "white" => loaded.white = parse_player(value)?,
+                "black" => loaded.black = parse_player(value)?,
+                "level" => loaded.level = parse_level(Some(value))?,
+                "uci-engine" => loaded.uci_engine = Some(value.to_string()),
+                "time-control" => time_control = Some(TimeControl::parse(value)?),
+                "clock" => clock_state = Some(value.to_string()),
+                "moves" => moves = value.split_whitespace().map(String::from).collect(),
+                //Later keys that leave the rules alone are skipped rather than refused
+                _ => {}
+            }
+        }
+
+        loaded.time_control = time_control.clone();
+        //Pieces last only as long as the run, so a game loaded later has to use the same ones
+        if let Some(path) = pieces {
+            match fairy::file() {
+                None => {
+                    fairy::load(path)?;
+                }
+                Some(v) if v == path => {}
+                Some(v) => {
+                    return Err(format!(
+                        "Saved game uses pieces from {path}, but those from {v} are loaded"
+                    ))
+                }
+            }
+            loaded.pieces = Some(path.to_string());
+        }
+        //Read once the variant is known, as it may bring pieces of its own
+        variant.load_pieces();
+        let mut start = start_fen.map_or(Ok(Position::startpos()), Position::from_fen)?;
+        start.chess960 |= chess960;
+        start.variant = variant;
+        let mut game = Game::new(start, time_control);
+
+        for text in &moves {
+            let mv = Move::from_uci(&game.position, text)
+                .ok_or(format!("Illegal move {text} in saved game"))?;
+            game.play(mv);
+        }
+
+        if let (Some(clock), Some(state)) = (game.clock.as_mut(), clock_state) {
+            let numbers = state
+                .split_whitespace()
+                .map(|x| x.parse::<u64>())
+                .collect::<Result<Vec<u64>, _>>()
+                .ok()
+                .filter(|x| x.len() == 6)
+                .ok_or(format!("Invalid clock {state} in saved game"))?;
+
+            clock.remaining = [
+                Duration::from_millis(numbers[0]),
+                Duration::from_millis(numbers[1]),
+            ];
+            clock.period = [numbers[2] as usize, numbers[3] as usize];
+            clock.moves = [numbers[4] as u32, numbers[5] as u32];
+        }
+
+        *settings = loaded;
+        Ok(game)
+    }
+
+    pub fn save(&self, path: &str, settings: &Settings, elapsed: Duration) -> Result<(), String> {
+        std::fs::write(path, self.to_save(settings, elapsed))
+            .map_err(|e| format!("Could not save to {path}: {e}"))
+    }
+
+    pub fn load(path: &str, settings: &mut Settings) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        Game::from_save(&text, settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_args, Player};
+
+    fn play(game: &mut Game, moves: &[&str]) {
+        for text in moves {
+            let mv = Move::from_uci(&game.position, text).unwrap();
+            game.play(mv);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut settings = parse_args(&["--black".into(), "engine".into()]).unwrap();
+        settings.level = 3;
+        let mut game = Game::new(Position::chess960(518), TimeControl::parse("5+3").ok());
+        play(&mut game, &["e2e4", "d7d5", "e4d5"]);
+        game.clock
+            .as_mut()
+            .unwrap()
+            .punch(true, Duration::from_secs(20));
+
+        let text = game.to_save(&settings, Duration::from_secs(4));
+        let mut loaded_settings = parse_args(&[]).unwrap();
+        let loaded = Game::from_save(&text, &mut loaded_settings).unwrap();
+
+        assert_eq!(loaded.position.hash, game.position.hash);
+        assert!(loaded.position.chess960);
+        assert!(loaded.moves == game.moves);
+        assert_eq!(loaded.fallen_pieces.len(), 1);
+        assert!(loaded_settings.black == Player::Engine);
+        assert_eq!(loaded_settings.level, 3);
+        assert_eq!(loaded_settings.time_control.unwrap().to_string(), "5+3");
+
+        //Black was on move when saved, so the time spent is charged to it
+        let clock = loaded.clock.unwrap();
+        assert_eq!(
+            clock.remaining,
+            [Duration::from_secs(283), Duration::from_secs(296)]
+        );
+        assert_eq!(clock.moves, [1, 0]);
+    }
+
+    #[test]
+    fn variant_round_trip() {
+        let settings = parse_args(&[]).unwrap();
+        let mut start = Position::startpos();
+        start.variant = Variant::Crazyhouse;
+        let mut game = Game::new(start, None);
+        play(&mut game, &["e2e4", "d7d5", "e4d5", "d8d5", "b1c3", "d5a5"]);
+        play(&mut game, &["P@d4"]);
+
+        let text = game.to_save(&settings, Duration::ZERO);
+        assert!(text.lines().any(|x| x == "variant crazyhouse"));
+        let loaded = Game::from_save(&text, &mut parse_args(&[]).unwrap()).unwrap();
+        assert!(loaded.position.variant == Variant::Crazyhouse);
+        assert_eq!(loaded.position.to_fen(), game.position.to_fen());
+    }
+
+    #[test]
+    fn version_1_save_loads() {
+        let text = "chess save 1\n\
+                    start rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1\n\
+                    white human\n\
+                    black engine\n\
+                    level 5\n\
+                    moves e2e4 e7e5 g1f3\n";
+        let mut settings = parse_args(&[]).unwrap();
+        let game = Game::from_save(text, &mut settings).unwrap();
+
+        assert_eq!(game.moves.len(), 3);
+        assert!(!game.position.white_to_move);
+        assert!(game.position.variant == Variant::Standard);
+        assert!(settings.black == Player::Engine);
+        assert_eq!(settings.level, 5);
+    }
+
+    #[test]
+    fn unreadable_saves_are_refused() {
+        let mut settings = parse_args(&[]).unwrap();
+        let error = |text: &str| Game::from_save(text, &mut settings.clone()).err().unwrap();
+
+        assert_eq!(
+            error("chess save 3\nmoves e2e4\n"),
+            "Saved game is version 3, newer than the supported 2"
+        );
+        assert_eq!(error("1. e4 e5\n"), "Not a saved game");
+        assert_eq!(
+            error("chess save 2\nmoves e2e5\n"),
+            "Illegal move e2e5 in saved game"
+        );
+        assert!(Game::from_save("chess save 2\nmoves\n", &mut settings).is_ok());
+    }
+}
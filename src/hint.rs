@@ -0,0 +1,116 @@
+use crate::bitboard::square_index;
+use crate::engine::piece_value;
+use crate::movegen::{in_check, legal_moves, make_move, Move};
+use crate::position::Position;
+use crate::{piece_name, Piece, PieceType};
+
+pub fn checkmated(position: &Position) -> bool {
+    in_check(position) && legal_moves(position).is_empty()
+}
+
+//Pieces of the team the other side attacks and nothing defends
+fn hanging(position: &Position, team_white: bool) -> Vec<Piece> {
+    position
+        .pieces
+        .iter()
+        .filter(|x| {
+            let square = square_index(x.position);
+            x.team_white == team_white
+                && x.piece_type != PieceType::King
+                && position.board.is_attacked(square, !team_white)
+                && !position.board.is_attacked(square, team_white)
+        })
+        .cloned()
+        .collect()
+}
+
+//Whether the side that just moved could mate next move if the opponent did nothing
+fn threatens_mate(after: &Position) -> bool {
+    if in_check(after) {
+        return false;
+    }
+
+    let mut passed = after.clone();
+    passed.white_to_move = !passed.white_to_move;
+    passed.en_passant = None;
+
+    legal_moves(&passed).into_iter().any(|mv| {
+        let mut next = passed.clone();
+        make_move(&mut next, mv);
+        checkmated(&next)
+    })
+}
+
+/*
+A short reason a move is good, for the first of these that applies: mate, winning material, a
+mate threat, check or saving a hanging piece. None if it's none of them.
+*/
+pub fn reason(position: &Position, mv: Move) -> Option<String> {
+    let team_white = position.white_to_move;
+    let moving = match mv.drop {
+        Some(v) => v,
+        None => position.piece_at(mv.from)?.piece_type,
+    };
+    let mut after = position.clone();
+    let undo = make_move(&mut after, mv);
+    let to = square_index(mv.to);
+
+    if checkmated(&after) {
+        return Some("checkmate".to_string());
+    }
+
+    //Material counts as won if the piece that took it can't simply be taken back
+    let landed = mv.promotion.unwrap_or(moving);
+    let at_risk = after.board.is_attacked(to, !team_white);
+    if let Some(captured) = &undo.captured {
+        let lost = if at_risk { piece_value(&landed) } else { 0 };
+        if piece_value(&captured.piece_type) > lost {
+            return Some(if at_risk {
+                format!(
+                    "wins a {} for a {}",
+                    piece_name(captured.piece_type),
+                    piece_name(landed)
+                )
+            } else {
+                format!("wins a {}", piece_name(captured.piece_type))
+            });
+        }
+    }
+    if let Some(promotion) = mv.promotion.filter(|_| !at_risk) {
+        return Some(format!("promotes to a {}", piece_name(promotion)));
+    }
+
+    if threatens_mate(&after) {
+        return Some("threatens mate".to_string());
+    }
+    if in_check(&after) {
+        return Some("gives check".to_string());
+    }
+
+    for piece in hanging(position, team_white) {
+        if piece.position == mv.from {
+            if !hanging(&after, team_white)
+                .iter()
+                .any(|x| x.position == mv.to)
+            {
+                return Some(format!(
+                    "saves the hanging {}",
+                    piece_name(piece.piece_type)
+                ));
+            }
+            continue;
+        }
+
+        let square = square_index(piece.position);
+        if !after.board.is_attacked(square, !team_white)
+            || after.board.is_attacked(square, team_white)
+        {
+            return Some(format!(
+                "defends the hanging {}",
+                piece_name(piece.piece_type)
+            ));
+        }
+    }
+
+    None
+}
@@ -1,6 +1,26 @@
+mod bitboard;
+mod book;
+mod clock;
+mod editor;
+mod engine;
+mod fairy;
+mod game;
+mod hint;
+mod movegen;
+mod pgn;
+mod position;
+mod puzzle;
+mod review;
+mod tablebase;
+mod uci;
+mod uci_client;
+mod variant;
+mod xboard;
+mod zobrist;
+
 const FORMAT_LABEL: &str = "(Format: columnrow, Example: D2)";
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum PieceType {
     Pawn,
     Rook,
@@ -8,6 +28,8 @@ enum PieceType {
     Bishop,
     King,
     Queen,
+    //One of the pieces loaded with --pieces, by its place in the file
+    Fairy(u8),
 }
 
 #[derive(Clone)]
@@ -17,435 +39,1257 @@ struct Piece {
     position: (u8, u8),
 }
 
-impl Piece {
-    fn move_piece(&self, where_to_move: (u8, u8), pieces: Vec<Piece>) -> Option<Self> {
-        //If any move conditions are met return piece with new position
-        if self
-            .move_conditions(where_to_move, pieces)
-            .iter()
-            .filter(|&&x| x == true)
-            .count()
-            != 0
-        {
-            Some(Piece {
-                position: where_to_move,
-                ..self.clone()
-            })
-        } else {
-            None
+#[derive(Clone, Copy, PartialEq)]
+enum Player {
+    Human,
+    Engine,
+    //Engine program given with --uci-engine
+    External,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Play,
+    Uci,
+    XBoard,
+}
+
+#[derive(Clone)]
+struct Settings {
+    mode: Mode,
+    white: Player,
+    black: Player,
+    level: u8,
+    uci_engine: Option<String>,
+    //None plays without clocks
+    time_control: Option<clock::TimeControl>,
+    //Saved game to pick up instead of starting a new one
+    resume: Option<String>,
+    //File the game is written to after every move
+    autosave: Option<String>,
+    //Polyglot opening book the engine plays from while it has moves
+    book: Option<String>,
+    book_pick: book::Pick,
+    //Directories with Syzygy endgame tables
+    syzygy: Option<String>,
+    //Number of the Chess960 start position to play from
+    chess960: Option<u16>,
+    variant: variant::Variant,
+    //File describing pieces beyond the usual six
+    pieces: Option<String>,
+    //Set up the position by hand before playing from it
+    edit: bool,
+}
+
+const USAGE: &str = "Usage: chess [uci|xboard] [--white human|engine|external] \
+                     [--black human|engine|external] [--level 1-10] [--uci-engine <path>] \
+                     [--time-control <control>] [--resume <file>] [--autosave <file>] \
+                     [--book <file>] [--book-pick weighted|best] [--syzygy <dir>] \
+                     [--chess960 0-959|random] [--variant <name>] [--pieces <file>] [--edit]";
+
+//Words typed in place of a location, the first one being the command name
+type Command = Vec<String>;
+//Crazyhouse drops typed in place of the piece to move come back as this command, e.g. DROP N F3
+const DROP: &str = "DROP";
+//Comes back in place of a move when the flag falls while it's being typed
+const FLAG: &str = "FLAG";
+const COMMANDS: [&str; 9] = [
+    "LEVEL", "ANALYSE", "ANALYZE", "CLOCK", "SAVE", "LOAD", "BOOK", "TB", "HINT",
+];
+
+//How long the external engine analyses when no time is given
+const ANALYSIS_TIME: std::time::Duration = std::time::Duration::from_secs(3);
+//Hints are kept short so nobody waits long for one
+const HINT_TIME: std::time::Duration = std::time::Duration::from_secs(1);
+//Search time for each position of a reviewed game when none is given
+const REVIEW_TIME: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn parse_level(level: Option<&str>) -> Result<u8, String> {
+    level
+        .and_then(|x| x.parse::<u8>().ok())
+        .filter(|x| (engine::MIN_LEVEL..=engine::MAX_LEVEL).contains(x))
+        .ok_or(format!(
+            "Level must be between {} and {}",
+            engine::MIN_LEVEL,
+            engine::MAX_LEVEL
+        ))
+}
+
+fn parse_player(name: &str) -> Result<Player, String> {
+    match name {
+        "human" => Ok(Player::Human),
+        "engine" => Ok(Player::Engine),
+        "external" => Ok(Player::External),
+        _ => Err(format!("Unknown player {name}, expected human, engine or external")),
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::Human => "human",
+        Player::Engine => "engine",
+        Player::External => "external",
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let mut settings = Settings {
+        mode: Mode::Play,
+        white: Player::Human,
+        black: Player::Human,
+        level: engine::MAX_LEVEL,
+        uci_engine: None,
+        time_control: None,
+        resume: None,
+        autosave: None,
+        book: None,
+        book_pick: book::Pick::Weighted,
+        syzygy: None,
+        chess960: None,
+        variant: variant::Variant::Standard,
+        pieces: None,
+        edit: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--white" | "--black" => {
+                let player = parse_player(
+                    args.next()
+                        .ok_or(format!("{arg} expects human, engine or external"))?,
+                )?;
+
+                if arg == "--white" {
+                    settings.white = player;
+                } else {
+                    settings.black = player;
+                }
+            }
+            "--level" => settings.level = parse_level(args.next().map(String::as_str))?,
+            "--uci-engine" => {
+                settings.uci_engine = Some(args.next().ok_or("--uci-engine expects a path")?.clone())
+            }
+            "--time-control" => {
+                settings.time_control = Some(clock::TimeControl::parse(
+                    args.next()
+                        .ok_or("--time-control expects a control like 5+3")?,
+                )?)
+            }
+            "--resume" => {
+                settings.resume = Some(args.next().ok_or("--resume expects a file")?.clone())
+            }
+            "--autosave" => {
+                settings.autosave = Some(args.next().ok_or("--autosave expects a file")?.clone())
+            }
+            "--book" => settings.book = Some(args.next().ok_or("--book expects a file")?.clone()),
+            "--book-pick" => {
+                settings.book_pick = match args.next().map(String::as_str) {
+                    Some("weighted") => book::Pick::Weighted,
+                    Some("best") => book::Pick::Best,
+                    _ => return Err("--book-pick expects weighted or best".to_string()),
+                }
+            }
+            "--syzygy" => {
+                settings.syzygy = Some(args.next().ok_or("--syzygy expects a directory")?.clone())
+            }
+            "--chess960" => {
+                let index = match args.next().map(String::as_str) {
+                    Some("random") => Some((engine::Rng::new().next_u64() % 960) as u16),
+                    v => v.and_then(|x| x.parse::<u16>().ok()).filter(|x| *x < 960),
+                };
+                settings.chess960 =
+                    Some(index.ok_or("--chess960 expects a number from 0 to 959 or random")?);
+            }
+            "--variant" => {
+                let name = args.next().ok_or("--variant expects a name")?;
+                settings.variant = variant::Variant::parse(name).ok_or(format!(
+                    "Unknown variant {name}, expected one of {}",
+                    variant::VARIANTS
+                        .map(|x| x.name())
+                        .join(", ")
+                ))?;
+            }
+            "--pieces" => {
+                settings.pieces = Some(args.next().ok_or("--pieces expects a file")?.clone())
+            }
+            "--edit" => settings.edit = true,
+            "uci" => settings.mode = Mode::Uci,
+            "xboard" => settings.mode = Mode::XBoard,
+            _ => return Err(format!("Unknown argument {arg}")),
         }
     }
 
-    fn move_conditions(&self, where_to_move: (u8, u8), pieces: Vec<Piece>) -> Vec<bool> {
-        let taken_positions = pieces
-            .iter()
-            .map(|piece| (piece.position.0, piece.position.1, piece.team_white))
-            .collect::<Vec<(u8, u8, bool)>>();
-
-        match self.piece_type {
-            PieceType::Pawn => vec![
-                //Move forward
-                where_to_move
-                    == (
-                        self.position.0,
-                        (self.position.1 as i8 + if self.team_white { -1 } else { 1 }) as u8,
-                    )
-                    && !taken_positions
-                        .iter()
-                        .map(|x| (x.0, x.1))
-                        .collect::<Vec<(u8, u8)>>()
-                        .contains(&(where_to_move.0, where_to_move.1)),
-                //Strike diagonally
-                taken_positions.contains(&(where_to_move.0, where_to_move.1, !self.team_white))
-                    && (where_to_move.0 as i8 - self.position.0 as i8).abs() == 1
-                    && where_to_move.1
-                        == (self.position.1 as i8 + if self.team_white { -1 } else { 1 }) as u8,
-                //Move forward two steps if unmoved
-                self.position.1 == if self.team_white { 6 } else { 1 }
-                    && where_to_move
-                        == (
-                            self.position.0,
-                            (self.position.1 as i8 + if self.team_white { -2 } else { 2 }) as u8,
-                        )
-                    && !taken_positions
-                        .iter()
-                        .map(|x| (x.0, x.1))
-                        .collect::<Vec<(u8, u8)>>()
-                        .contains(&(where_to_move.0, where_to_move.1))
-                    //Prevent from moving over pieces
-                    && !taken_positions
-                        .iter()
-                        .map(|x| (x.0, x.1))
-                        .collect::<Vec<(u8, u8)>>()
-                        .contains(&(where_to_move.0, (where_to_move.1 as i8 + if self.team_white {1} else {-1}) as u8 )),
-            ],
-            PieceType::Rook => vec![
-                //Move vertically
-                where_to_move.0 == self.position.0
-                    && where_to_move.1 as i8 - self.position.1 as i8 != 0
-                    && 
-                    //Ensure range begins with the smaller position
-                    if where_to_move.1 > self.position.1 {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            self.position.1..where_to_move.1
-                        } else {
-                            self.position.1..where_to_move.1 + 1
-                        }
-                    } else {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            where_to_move.1 + 1..self.position.1
-                        } else {
-                            where_to_move.1..self.position.1
-                        }
+    if settings.uci_engine.is_none()
+        && (settings.white == Player::External || settings.black == Player::External)
+    {
+        return Err("External players need an engine given with --uci-engine".to_string());
+    }
+    if settings.chess960.is_some() && settings.variant != variant::Variant::Standard {
+        return Err("Chess960 is only played with standard rules".to_string());
+    }
+    if settings.edit && (settings.mode != Mode::Play || settings.resume.is_some()) {
+        return Err("--edit sets up a new game to play, not a resumed one or an engine".to_string());
+    }
+
+    Ok(settings)
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    //Subcommands that do a job and exit rather than play
+    if args.first().is_some_and(|x| x == "build-book") {
+        if !book::run_builder(&args[1..]) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().is_some_and(|x| x == "mine-puzzles") {
+        if !puzzle::run_miner(&args[1..]) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().is_some_and(|x| x == "puzzles") {
+        if !puzzle::run(&args[1..]) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut settings = match parse_args(&args) {
+        Ok(v) => v,
+        Err(e) => {
+            println!(
+                "{e}\n{USAGE}\n{}\n{}\n{}",
+                book::BUILD_USAGE,
+                puzzle::USAGE,
+                puzzle::MINE_USAGE
+            );
+            return;
+        }
+    };
+
+    //Loaded first so positions can use the pieces, the file may also give the position to play
+    let fairy_start = match &settings.pieces {
+        Some(path) => match fairy::load(path) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => None,
+    };
+    let start = match &fairy_start {
+        Some(fen) => position::Position::from_fen(fen).map(|mut v| {
+            if v.variant == variant::Variant::Standard {
+                v.variant = settings.variant;
+            }
+            v
+        }),
+        None => settings.chess960.map_or_else(
+            || settings.variant.start(),
+            |x| Ok(position::Position::chess960(x)),
+        ),
+    };
+    let start = match start {
+        //Whatever the other options gave is where setting up begins
+        Ok(v) if settings.edit => editor::run(v),
+        Ok(v) => v,
+        Err(e) => {
+            println!("{e}");
+            return;
+        }
+    };
+
+    let book = match &settings.book {
+        Some(path) => match book::Book::open(path) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let tablebase = match &settings.syzygy {
+        Some(path) => match tablebase::Tablebase::open(path) {
+            Ok(v) => Some(std::sync::Arc::new(v)),
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    match settings.mode {
+        Mode::Uci => return uci::run(settings.level, book, settings.book_pick, tablebase),
+        Mode::XBoard => return xboard::run(settings.level, book, settings.book_pick, tablebase),
+        Mode::Play => {}
+    }
+
+    let mut game = match &settings.resume {
+        Some(path) => match game::Game::load(&path.clone(), &mut settings) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => game::Game::new(start, settings.time_control.clone()),
+    };
+    if game.start.variant != variant::Variant::Standard {
+        println!(
+            "{}: {}",
+            game.start.variant.title(),
+            game.start.variant.goal()
+        );
+    }
+    if game.start.chess960 {
+        if let Some(index) = settings.chess960.filter(|_| settings.resume.is_none()) {
+            println!("Chess960 start position {index}");
+        }
+        println!("Castle by moving the king onto the rook it castles with");
+    }
+
+    let mut rng = engine::Rng::new();
+    let stop = std::sync::atomic::AtomicBool::new(false);
+
+    //Kept across commands so the clock keeps running while they're typed
+    let mut turn_started = std::time::Instant::now();
+    //Last hint with the position it was for, marked on the board until a move is made
+    let mut hint: Option<(u64, movegen::Move)> = None;
+
+    let mut external = match &settings.uci_engine {
+        Some(path) => match uci_client::UciEngine::start(path) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let outcome = loop {
+        let team_white = game.position.white_to_move;
+        let board = match hint.filter(|x| x.0 == game.position.hash) {
+            Some((_, v)) => highlight_squares(
+                &render_board(&game.position),
+                &[v.from, v.to],
+                game.position.top(),
+            ),
+            None => render_board(&game.position),
+        };
+        let mut panel = game.clock.as_ref().map_or(vec![], |x| {
+            clock_panel(x, team_white, turn_started.elapsed(), game.position.height)
+        });
+        panel.extend(variant_panel(&game.position));
+        println!("{}", beside_board(&board, &panel));
+
+        let legal_moves = movegen::legal_moves(&game.position);
+
+        if let Some(outcome) = movegen::outcome(&game.position) {
+            break outcome;
+        }
+        //The flag can fall while a command is being typed
+        if game
+            .clock
+            .as_ref()
+            .is_some_and(|x| x.flagged(team_white, turn_started.elapsed()))
+        {
+            break movegen::timeout(&game.position, team_white);
+        }
+
+        println!(
+            "{}",
+            if team_white {
+                "White's turn"
+            } else {
+                "Black's turn"
+            }
+        );
+
+        let player = if team_white {
+            settings.white
+        } else {
+            settings.black
+        };
+        let time = move_time(game.clock.as_ref(), team_white, turn_started.elapsed());
+
+        let next_move = match player {
+            Player::Human => {
+                let clock = game.clock.as_ref().map(|x| (x, turn_started));
+                match take_move_input(&game.position, &legal_moves, team_white, clock) {
+                    Ok(v) => v,
+                    Err(command) if command[0] == FLAG => {
+                        break movegen::timeout(&game.position, team_white);
                     }
-                    //Ensure no pieces in the path
-                    .filter(|i| {
-                        taken_positions
-                            .iter()
-                            .filter(|x| x.0 == self.position.0)
-                            .map(|j| j.1)
-                            .collect::<Vec<u8>>()
-                            .contains(&i)
-                    })
-                    .count()
-                        == 0,
-                //Move horizontally
-                where_to_move.1 == self.position.1
-                    && where_to_move.0 as i8 - self.position.0 as i8 != 0
-                    && 
-                    //Ensure range begins with the lower number
-                    if where_to_move.0 > self.position.0 {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            self.position.0..where_to_move.0
-                        } else {
-                            self.position.0..where_to_move.0 + 1
-                        }
-                    } else {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            where_to_move.0 + 1..self.position.0
-                        } else {
-                            where_to_move.0..self.position.0
-                        }
+                    Err(command) if command[0] == "HINT" => {
+                        let mv = suggest_move(&game.position, book.as_ref(), tablebase.as_deref());
+                        hint = Some((game.position.hash, mv));
+                        continue;
                     }
-                    //Ensure no pieces in path
-                    .filter(|i| {
-                        taken_positions
-                            .iter()
-                            .filter(|x| x.1 == self.position.1)
-                            .map(|j| j.0)
-                            .collect::<Vec<u8>>()
-                            .contains(&i)
-                    })
-                    .count()
-                        == 0,
-            ],
-            PieceType::Knight => vec![
-                //L shape movement; horizontal 2 vertical 1
-                (where_to_move.0 as i8 - self.position.0 as i8).abs() == 2
-                    && (where_to_move.1 as i8 - self.position.1 as i8).abs() == 1
-                    //Ensure not striking teammate
-                    && !taken_positions.contains(&(
-                        where_to_move.0,
-                        where_to_move.1,
-                        self.team_white,
-                    )),
-                //L shape movement; vertical 2 horizontal 1
-                (where_to_move.1 as i8 - self.position.1 as i8).abs() == 2
-                    && (where_to_move.0 as i8 - self.position.0 as i8).abs() == 1
-                    //Ensure not striking teammate
-                    && !taken_positions.contains(&(
-                        where_to_move.0,
-                        where_to_move.1,
-                        self.team_white,
-                    )),
-            ],
-            PieceType::Bishop => vec![
-                //Ensure moving
-                where_to_move.1 as i8 - self.position.1 as i8 != 0
-                    //Ensure diagonal
-                    && (where_to_move.0 as i8 - self.position.0 as i8).abs()
-                        == (where_to_move.1 as i8 - self.position.1 as i8).abs()
-                    //Ensure range begins with lower number
-                    && if where_to_move.0 > self.position.0 {
-                        //Allow striking while preventing from striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            self.position.0..where_to_move.0
-                        } else {
-                            self.position.0..where_to_move.0 + 1
-                        }
-                    } else {
-                        //Allow striking while preventing from striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            where_to_move.0 + 1..self.position.0
-                        } else {
-                            where_to_move.0..self.position.0
-                        }
+                    Err(command) => {
+                        run_command(
+                            &command,
+                            &mut settings,
+                            &mut game,
+                            &mut external,
+                            book.as_ref(),
+                            tablebase.as_deref(),
+                            &mut turn_started,
+                        );
+                        continue;
                     }
-                    //Ensure no pieces in the path; this part checks for same x pos
-                    .filter(|i| {
-                        taken_positions
-                            .iter()
-                            .filter(|j| {
-                                (self.position.1 as i8 - j.1 as i8).abs()
-                                    == (self.position.0 as i8 - j.0 as i8).abs()
-                                    && if where_to_move.0 > self.position.0 {
-                                        j.0 > self.position.0 && j.0 <= where_to_move.0
-                                    } else {
-                                        j.0 < self.position.0 && j.0 >= where_to_move.0
-                                    }
-                                    && if where_to_move.1 > self.position.1 {
-                                        j.1 > self.position.1 && j.1 <= where_to_move.1
-                                    } else {
-                                        j.1 < self.position.1 && j.1 >= where_to_move.1
-                                    }
-                            })
-                            .map(|x| x.0)
-                            .collect::<Vec<u8>>()
-                            .contains(&i)
-                    })
-                    .count()
-                        == 0
-                    //Ensure range begins with lower number
-                    && if where_to_move.1 > self.position.1 {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            self.position.1..where_to_move.1
-                        } else {
-                            self.position.1..where_to_move.1 + 1
-                        }
+                }
+            }
+            Player::Engine => engine_move(
+                &game.position,
+                &settings,
+                book.as_ref(),
+                tablebase.as_deref(),
+                time,
+                &stop,
+                &mut rng,
+            ),
+            Player::External => match external.as_mut().map_or(
+                Err("The external engine is not running".to_string()),
+                |x| x.best_move(&game, time, &mut |_| {}),
+            ) {
+                Ok(v) => {
+                    println!(
+                        "{} moves {}",
+                        external.as_ref().unwrap().name,
+                        describe_move(v, game.position.top())
+                    );
+                    v
+                }
+                //Keep the game going with the built-in engine rather than ending it
+                Err(e) => {
+                    println!("{e}. The built-in engine takes over");
+                    external = None;
+                    if team_white {
+                        settings.white = Player::Engine;
                     } else {
-                        //Allow striking while preventing striking teammate
-                        if taken_positions.contains(&(
-                            where_to_move.0,
-                            where_to_move.1,
-                            !self.team_white,
-                        )) {
-                            where_to_move.1 + 1..self.position.1
-                        } else {
-                            where_to_move.1..self.position.1
-                        }
+                        settings.black = Player::Engine;
                     }
-                    //Ensure no pieces in path; this part checks for same y pos
-                    .filter(|i| {
-                        taken_positions
-                            .iter()
-                            .filter(|j| {
-                                (self.position.1 as i8 - j.1 as i8).abs()
-                                    == (self.position.0 as i8 - j.0 as i8).abs()
-                                    && if where_to_move.0 > self.position.0 {
-                                        j.0 > self.position.0 && j.0 <= where_to_move.0
-                                    } else {
-                                        j.0 < self.position.0 && j.0 >= where_to_move.0
-                                    }
-                                    && if where_to_move.1 > self.position.1 {
-                                        j.1 > self.position.1 && j.1 <= where_to_move.1
-                                    } else {
-                                        j.1 < self.position.1 && j.1 >= where_to_move.1
-                                    }
-                            })
-                            .map(|x| x.1)
-                            .collect::<Vec<u8>>()
-                            .contains(&i)
-                    })
-                    .count()
-                        == 0,
-            ],
-            PieceType::Queen => [
-                //Combine rook and bishop conditions 
-                //Will have to remove castling once implemented.
-                Piece {
-                    team_white: self.team_white,
-                    position: self.position,
-                    piece_type: PieceType::Rook,
-                }
-                .move_conditions(where_to_move, pieces.clone()),
-                Piece {
-                    team_white: self.team_white,
-                    position: self.position,
-                    piece_type: PieceType::Bishop,
+                    engine_move(
+                        &game.position,
+                        &settings,
+                        book.as_ref(),
+                        tablebase.as_deref(),
+                        move_time(game.clock.as_ref(), team_white, turn_started.elapsed()),
+                        &stop,
+                        &mut rng,
+                    )
                 }
-                .move_conditions(where_to_move, pieces),
-            ]
-            .concat(),
-            PieceType::King => vec![
-                //Ensure king can't move into danger
-                pieces
-                    .iter()
-                    .filter(|piece| piece.team_white != self.team_white)
-                    .find(|piece| {
-                        if piece.piece_type != PieceType::Pawn {
-                            piece.move_piece(where_to_move, pieces.clone()).is_some()
-                        } else {
-                            //Check pawn striking separately since pawns strike differently than their regular movement
-                            
-                            /*
-                            Combine a vec with king's potential new position and the original vec
-                            without the king (I'm aware this is probably the worst imaginible way to do this)
-                            */
-                            piece.move_conditions(where_to_move, vec![
-                                vec![
-                                Piece {
-                                    position: where_to_move,
-                                    ..self.clone()}
-                                ],
-                                pieces
-                                    .iter()
-                                    .filter(|x| x.position != self.position)
-                                        .map(|x| x.to_owned())
-                                        .collect::<Vec<_>>()
-                            ].concat())[1]
-                        }
-                    })
-                    .is_none()
-                    //Ensure not striking teammate
-                    && !taken_positions.contains(&(
-                        where_to_move.0,
-                        where_to_move.1,
-                        self.team_white,
-                    ))
-                    //Move 1 space in any direction
-                    && where_to_move != self.position
-                    && (where_to_move.0 as i8 - self.position.0 as i8).abs() <= 1
-                    && (where_to_move.1 as i8 - self.position.1 as i8).abs() <= 1,
-            ],
-        }
-    //TODO: castling, pins, promotions, en passant
+            },
+        };
+
+        //A move made after the flag fell doesn't count
+        if let Some(v) = game.clock.as_mut() {
+            if !v.punch(team_white, turn_started.elapsed()) {
+                break movegen::timeout(&game.position, team_white);
+            }
+        }
+        turn_started = std::time::Instant::now();
+
+        game.play(next_move);
+
+        if let Some(path) = &settings.autosave {
+            if let Err(e) = game.save(path, &settings, std::time::Duration::ZERO) {
+                println!("{e}");
+            }
+        }
+    };
 
+    announce(&outcome);
+    offer_review(
+        &game,
+        &settings,
+        external.as_ref().map(|x| x.name.as_str()),
+        &outcome,
+    );
+}
+
+fn announce(outcome: &movegen::Outcome) {
+    println!(
+        "{}! {}",
+        outcome.reason(),
+        match outcome {
+            movegen::Outcome::WhiteWins(_) => "White wins",
+            movegen::Outcome::BlackWins(_) => "Black wins",
+            movegen::Outcome::Draw(_) => "It's a draw",
+        }
+    );
+}
+
+/*
+Once the game is over it can be gone through with the engine: REVIEW, optionally followed by the
+seconds to spend on each position and a file to write the annotated game to as PGN.
+*/
+fn offer_review(
+    game: &game::Game,
+    settings: &Settings,
+    external_name: Option<&str>,
+    outcome: &movegen::Outcome,
+) {
+    if game.moves.is_empty() {
+        return;
+    }
+    println!("Type REVIEW [seconds per position] [file.pgn] to review the game");
+    let input = read_input();
+    let words = input.split_whitespace().collect::<Vec<&str>>();
+    if !words.first().is_some_and(|x| x.eq_ignore_ascii_case("REVIEW")) {
+        return;
+    }
+
+    let mut time = REVIEW_TIME;
+    let mut path = None;
+    for word in &words[1..] {
+        match word.parse::<f64>() {
+            Ok(v) => time = std::time::Duration::from_secs_f64(v.max(0.1)),
+            Err(_) => path = Some(*word),
+        }
+    }
+
+    let reviews = review::review(&game.start, &game.moves, time);
+
+    let mut number = game.start.fullmove_number;
+    for x in &reviews {
+        let clamp = |score: i32| score.clamp(-review::SCORE_CAP, review::SCORE_CAP);
+        let mut line = format!(
+            "{:>4}{:<4}{:<7} {:>7} {:>+7.2}",
+            number,
+            if x.team_white { "." } else { "..." },
+            format_move(x.mv, game.start.top()),
+            if x.after.abs() == engine::MATE_SCORE {
+                "mate".to_string()
+            } else {
+                engine::format_score(x.after)
+            },
+            (clamp(x.after) - clamp(x.before)) as f64 / 100.0
+        );
+        if let (Some(judgement), Some((best, _))) = (x.judgement, &x.best) {
+            line += &format!(
+                "  {}, best was {} ({})",
+                judgement.name(),
+                format_move(*best, game.start.top()),
+                engine::format_score(x.before)
+            );
+        }
+        println!("{line}");
+
+        if !x.team_white {
+            number += 1;
+        }
+    }
+    println!("{}", review::summary(&reviews, true));
+    println!("{}", review::summary(&reviews, false));
+
+    let Some(path) = path else {
+        return;
+    };
+    let name = |player: Player| match player {
+        Player::Human => "Human".to_string(),
+        Player::Engine => format!("Engine level {}", settings.level),
+        Player::External => external_name.unwrap_or("External engine").to_string(),
+    };
+    let tags = [
+        ("Event", "Casual game".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", pgn::today()),
+        ("Round", "-".to_string()),
+        ("White", name(settings.white)),
+        ("Black", name(settings.black)),
+        ("Result", outcome.result().to_string()),
+        ("Annotator", format!("Engine level {}", engine::MAX_LEVEL)),
+    ]
+    .map(|(name, value)| (name.to_string(), value));
+
+    let text = review::annotated_pgn(&tags, &game.start, &reviews, outcome.result());
+    match std::fs::write(path, text) {
+        Ok(()) => println!("Annotated game written to {path}"),
+        Err(e) => println!("Could not write {path}: {e}"),
     }
 }
 
-fn main() {
-    let mut turn = 0usize;
+//Thinking time for an engine, planned from its clock when there is one
+fn move_time(
+    clock: Option<&clock::Clock>,
+    team_white: bool,
+    elapsed: std::time::Duration,
+) -> std::time::Duration {
+    match clock {
+        Some(v) => engine::time_for_move(
+            v.remaining_after(team_white, elapsed),
+            v.bonus(team_white),
+            v.moves_to_go(team_white)
+                .unwrap_or(engine::DEFAULT_MOVES_TO_GO),
+        ),
+        None => engine::DEFAULT_MOVE_TIME,
+    }
+}
 
-    let mut current_pieces = default_pieces();
-    let mut fallen_pieces = vec![];
+//Clock lines to show beside the board, black's by its side at the top and white's at the bottom
+fn clock_panel(
+    clock: &clock::Clock,
+    white_to_move: bool,
+    elapsed: std::time::Duration,
+    rows: u8,
+) -> Vec<(usize, String)> {
+    let line = |team_white: bool| {
+        let running = team_white == white_to_move;
+        format!(
+            "   {} {}{}",
+            if team_white { "White" } else { "Black" },
+            clock::format_duration(clock.remaining_after(
+                team_white,
+                if running {
+                    elapsed
+                } else {
+                    std::time::Duration::ZERO
+                }
+            )),
+            match clock.moves_to_go(team_white) {
+                Some(v) => format!(" ({v} moves to go)"),
+                None => String::new(),
+            } + if running { " <" } else { "" }
+        )
+    };
 
-    loop {
-        println!("{}", render_board(current_pieces.clone()));
-        
-        println!("{}", if turn % 2 == 0 {
-            "White's turn"
-        } else {
-            "Black's turn"
-        });
+    vec![(1, line(false)), (rows as usize, line(true))]
+}
 
-        fn take_position_input(current_pieces: Vec<Piece>, should_contain_piece: bool) -> (u8, u8) {
-            loop {
-                let mut piece_to_move = String::new();
-                std::io::stdin().read_line(&mut piece_to_move).unwrap();
-                piece_to_move = piece_to_move.to_uppercase();
+//Which rules are being played, shown halfway down the side of the board
+fn variant_panel(position: &position::Position) -> Vec<(usize, String)> {
+    let mut panel = match position.variant {
+        variant::Variant::Standard => vec![],
+        v => vec![(4, format!("   {}", v.title()))],
+    };
 
-                let proper_format_regex = regex::Regex::new("^[A-H][1-8]$").unwrap();
-                if proper_format_regex.is_match(piece_to_move.trim()) {
-                    if current_pieces
-                        .iter()
-                        .map(|x| x.position)
-                        .collect::<Vec<(u8, u8)>>()
-                        .contains(&parse_location(piece_to_move.trim()))
-                        || !should_contain_piece
-                    {
-                        break parse_location(piece_to_move.trim());
-                    } else {
-                        println!("No piece in that location. Please try again... {FORMAT_LABEL}");
-                    }
+    //Three-check counters go on each side's end of the board, inside the clocks
+    if position.variant == variant::Variant::ThreeCheck {
+        let checks = |team_white: bool| {
+            format!(
+                "   Checks given: {} of 3",
+                position.checks[!team_white as usize]
+            )
+        };
+        panel.push((2, checks(false)));
+        panel.push((7, checks(true)));
+    }
+
+    //Crazyhouse pockets go on the owner's side the same way
+    if position.variant == variant::Variant::Crazyhouse {
+        let pocket = |team_white: bool| {
+            let pieces = variant::POCKET
+                .into_iter()
+                .zip(position.pockets[!team_white as usize])
+                .filter(|x| x.1 > 0)
+                .map(|(piece_type, count)| match count {
+                    1 => piece_glyph(piece_type, team_white).to_string(),
+                    _ => format!("{}{count}", piece_glyph(piece_type, team_white)),
+                })
+                .collect::<Vec<String>>();
+            format!(
+                "   In hand: {}",
+                if pieces.is_empty() {
+                    "nothing".to_string()
                 } else {
-                    println!("Incorrect format. Please try again... {FORMAT_LABEL}");
+                    pieces.join(" ")
+                }
+            )
+        };
+        panel.push((2, pocket(false)));
+        panel.push((7, pocket(true)));
+    }
+
+    panel
+}
+
+//Brackets squares on a rendered board, like [E2], its first row being the given one
+fn highlight_squares(board: &str, squares: &[(u8, u8)], top: u8) -> String {
+    board
+        .lines()
+        .enumerate()
+        .map(|(i, v)| {
+            let mut chars = v.chars().collect::<Vec<char>>();
+            //The first line is the column labels, each square has a space on either side
+            for square in squares.iter().filter(|x| (x.1 - top) as usize + 1 == i) {
+                let at = 3 + 2 * square.0 as usize;
+                //Squares side by side share the space between them
+                chars[at - 1] = if chars[at - 1] == ']' { '|' } else { '[' };
+                chars[at + 1] = if chars[at + 1] == '[' { '|' } else { ']' };
+            }
+            chars.into_iter().collect::<String>() + "\n"
+        })
+        .collect()
+}
+
+//Appends text to the end of the given lines of a rendered board
+fn beside_board(board: &str, panel: &[(usize, String)]) -> String {
+    //Rows are padded to the same length so the text lines up
+    let width = board.lines().map(|x| x.chars().count()).max().unwrap_or(0);
+
+    board
+        .lines()
+        .enumerate()
+        .map(|(i, v)| {
+            let text = panel
+                .iter()
+                .filter(|x| x.0 == i)
+                .map(|x| x.1.as_str())
+                .collect::<String>();
+            if text.is_empty() {
+                format!("{v}\n")
+            } else {
+                format!("{v:width$}{text}\n")
+            }
+        })
+        .collect()
+}
+
+fn engine_move(
+    position: &position::Position,
+    settings: &Settings,
+    book: Option<&book::Book>,
+    tablebase: Option<&tablebase::Tablebase>,
+    time: std::time::Duration,
+    stop: &std::sync::atomic::AtomicBool,
+    rng: &mut engine::Rng,
+) -> movegen::Move {
+    if let Some(mv) = book.and_then(|x| x.pick(position, settings.book_pick, rng)) {
+        println!(
+            "Engine plays book move {}",
+            describe_move(mv, position.top())
+        );
+        return mv;
+    }
+    //Endgames in the tables are played perfectly whatever the level
+    if let Some((mv, wdl, dtz)) = tablebase.and_then(|x| x.best_move(position)) {
+        println!(
+            "Engine moves {} ({})",
+            describe_move(mv, position.top()),
+            tablebase::describe(wdl, Some(dtz))
+        );
+        return mv;
+    }
+
+    let result = engine::search(
+        position,
+        &engine::Difficulty::from_level(settings.level),
+        &engine::Limits {
+            time: Some(time),
+            ..Default::default()
+        },
+        stop,
+        rng,
+        &mut |_| {},
+    );
+    //There is always a best move since the side to move has legal moves
+    let next_move = result.best_move.unwrap();
+
+    println!(
+        "Engine moves {} (evaluation: {}, depth {}, {} nodes)",
+        describe_move(next_move, position.top()),
+        engine::format_score(result.score),
+        result.depth,
+        result.nodes
+    );
+
+    next_move
+}
+
+//A move for the side to move with why it's good, from a quick search at full strength
+fn suggest_move(
+    position: &position::Position,
+    book: Option<&book::Book>,
+    tablebase: Option<&tablebase::Tablebase>,
+) -> movegen::Move {
+    let mut rng = engine::Rng::new();
+    let book_move = book.and_then(|x| x.pick(position, book::Pick::Best, &mut rng));
+    let (mv, reason) = if let Some(mv) = book_move {
+        (mv, Some("the main line in the opening book".to_string()))
+    } else if let Some((mv, wdl, dtz)) = tablebase.and_then(|x| x.best_move(position)) {
+        (mv, Some(tablebase::describe(wdl, Some(dtz))))
+    } else {
+        let result = engine::search(
+            position,
+            &engine::Difficulty::from_level(engine::MAX_LEVEL),
+            &engine::Limits {
+                time: Some(HINT_TIME),
+                ..Default::default()
+            },
+            &std::sync::atomic::AtomicBool::new(false),
+            &mut rng,
+            &mut |_| {},
+        );
+        //The game loop only asks while there are moves to make
+        let mv = result.best_move.unwrap();
+        (mv, hint::reason(position, mv))
+    };
+
+    println!(
+        "Hint: {}{}",
+        describe_move(mv, position.top()),
+        reason.map_or(String::new(), |x| format!(", {x}"))
+    );
+    mv
+}
+
+fn run_command(
+    command: &Command,
+    settings: &mut Settings,
+    game: &mut game::Game,
+    external: &mut Option<uci_client::UciEngine>,
+    book: Option<&book::Book>,
+    tablebase: Option<&tablebase::Tablebase>,
+    turn_started: &mut std::time::Instant,
+) {
+    let position = &game.position;
+    match command[0].as_str() {
+        "CLOCK" => match &game.clock {
+            Some(v) => {
+                println!("Time control {}", v.control);
+                let elapsed = turn_started.elapsed();
+                for (_, line) in clock_panel(v, position.white_to_move, elapsed, position.height) {
+                    println!("{}", line.trim_start());
                 }
             }
+            None => println!("This game is played without clocks"),
+        },
+        "BOOK" => {
+            let Some(book) = book else {
+                println!("No opening book given, use --book");
+                return;
+            };
+
+            let moves = book.moves(position);
+            if moves.is_empty() {
+                println!("No book moves for this position");
+            }
+            let total = moves.iter().map(|x| x.1 as u64).sum::<u64>().max(1);
+            for (mv, weight) in moves {
+                println!(
+                    "  {:<7} weight {:>5} ({:.1}%)",
+                    format_move(mv, position.top()),
+                    weight,
+                    weight as f64 * 100.0 / total as f64
+                );
+            }
         }
+        "TB" => {
+            let Some(tablebase) = tablebase else {
+                println!("No tablebases given, use --syzygy");
+                return;
+            };
+            let Some(moves) = tablebase.rank_moves(position) else {
+                println!(
+                    "No tables for this position, they cover up to {} pieces without castling",
+                    tablebase.largest
+                );
+                return;
+            };
 
-        println!("Choose piece to move... {FORMAT_LABEL}");
-        let mut piece_to_move = take_position_input(current_pieces.clone(), true);
+            //The best move's result is the position's
+            println!("Position: {}", tablebase::describe(moves[0].1, moves[0].2));
+            for (mv, wdl, dtz) in moves {
+                println!(
+                    "  {:<7} {:<12} DTZ {}",
+                    format_move(mv, position.top()),
+                    wdl.name(),
+                    dtz.map_or("unknown".to_string(), |x| x.to_string())
+                );
+            }
+        }
+        "SAVE" => match command.get(1).or(settings.autosave.as_ref()) {
+            Some(path) => match game.save(path, settings, turn_started.elapsed()) {
+                Ok(()) => println!("Game saved to {path}"),
+                Err(e) => println!("{e}"),
+            },
+            None => println!("Save expects a file"),
+        },
+        "LOAD" => {
+            let Some(path) = command.get(1) else {
+                println!("Load expects a file");
+                return;
+            };
 
-        let old_piece = current_pieces.remove(loop {
-            match current_pieces
-                .iter()
-                .position(|x| x.position == piece_to_move)
-            {
-                Some(v) =>
-                    if current_pieces[v].team_white == (turn % 2 == 0) {
-                        break v
-                    } else {
-                        println!("Not your turn! Choose piece to move again... {FORMAT_LABEL}");
-                        piece_to_move = take_position_input(current_pieces.clone(), true);
-                        continue;
+            let previous_engine = settings.uci_engine.clone();
+            match game::Game::load(path, settings) {
+                Ok(v) => {
+                    *game = v;
+                    *turn_started = std::time::Instant::now();
+                    println!("Game loaded from {path}");
+                }
+                Err(e) => {
+                    println!("{e}");
+                    return;
+                }
+            }
+
+            if settings.uci_engine != previous_engine {
+                *external = None;
+                if let Some(path) = &settings.uci_engine {
+                    match uci_client::UciEngine::start(path) {
+                        Ok(v) => *external = Some(v),
+                        Err(e) => println!("{e}"),
                     }
-                None => {
-                    println!("No piece in that position. Choose again... {FORMAT_LABEL}");
-                    piece_to_move = take_position_input(current_pieces.clone(), true);
-                    continue;
                 }
             }
+        }
+        "LEVEL" => match command.get(1) {
+            Some(level) => match parse_level(Some(level)) {
+                Ok(v) => {
+                    settings.level = v;
+                    println!("Engine level set to {v}");
+                }
+                Err(e) => println!("{e}"),
+            },
+            None => println!("Engine level is {}", settings.level),
+        },
+        "ANALYSE" | "ANALYZE" => {
+            let probe =
+                tablebase.and_then(|x| Some((x.probe_wdl(position)?, x.probe_dtz(position))));
+            if let Some((wdl, dtz)) = probe {
+                println!("  {}", tablebase::describe(wdl, dtz));
+            }
+
+            let Some(engine) = external else {
+                //The tables already said all there is to say
+                if probe.is_none() {
+                    println!("Analysis needs an engine given with --uci-engine");
+                }
+                return;
+            };
+
+            let time = command
+                .get(1)
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(ANALYSIS_TIME, |x| {
+                    std::time::Duration::from_secs_f64(x.max(0.1))
+                });
+
+            println!("Analysis by {}", engine.name);
+            //Scores come from the side to move's point of view
+            let sign = if position.white_to_move { 1 } else { -1 };
+            if let Err(e) = engine.analyse(game, time, &mut |line| {
+                println!(
+                    "  depth {:>2}  {:>7}  {}",
+                    line.depth,
+                    engine::format_score(line.score * sign),
+                    line.pv
+                        .iter()
+                        .map(|&x| format_move(x, position.top()))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                )
+            }) {
+                println!("{e}");
+                *external = None;
+            }
+        }
+        _ => {}
+    }
+}
+
+//Lines are read on a thread of their own so that waiting for a move can give up when the flag falls
+fn input_lines() -> &'static std::sync::Mutex<std::sync::mpsc::Receiver<String>> {
+    static LINES: std::sync::OnceLock<std::sync::Mutex<std::sync::mpsc::Receiver<String>>> =
+        std::sync::OnceLock::new();
+    LINES.get_or_init(|| {
+        let (sender, lines) = std::sync::mpsc::channel();
+        std::thread::spawn(move || loop {
+            let mut input = String::new();
+            match std::io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if sender.send(input).is_err() => break,
+                Ok(_) => {}
+            }
         });
+        std::sync::Mutex::new(lines)
+    })
+}
 
-        println!("Choose where to move... {FORMAT_LABEL}");
-        let mut where_to_move = take_position_input(current_pieces.clone(), false);
+//Ends the game quietly once input runs out, e.g. when moves are piped in
+fn read_input() -> String {
+    input_lines()
+        .lock()
+        .unwrap()
+        .recv()
+        .unwrap_or_else(|_| std::process::exit(0))
+}
 
-        let new_piece = loop {
-            match old_piece.move_piece(where_to_move, current_pieces.clone()) {
-                Some(v) => break v,
-                None => {
-                    println!(
-                        "Cannot move piece there. Choose where to move again... {FORMAT_LABEL}"
-                    );
-                    where_to_move = take_position_input(current_pieces.clone(), false);
-                    continue;
+/*
+Reads a move while the mover's clock runs, given with the instant their turn started. In a terminal
+the running clock is shown on the line above the input and redrawn as it ticks. None once the flag
+falls, without waiting for Enter.
+*/
+fn read_move_input(
+    position: &position::Position,
+    clock: Option<(&clock::Clock, std::time::Instant)>,
+) -> Option<String> {
+    use std::io::{IsTerminal, Write};
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let Some((clock, started)) = clock else {
+        return Some(read_input());
+    };
+    let team_white = position.white_to_move;
+    let terminal = std::io::stdout().is_terminal();
+    let line = || {
+        clock_panel(clock, team_white, started.elapsed(), 1)[team_white as usize]
+            .1
+            .clone()
+    };
+    if terminal {
+        println!("{}", line());
+    }
+
+    let lines = input_lines().lock().unwrap();
+    loop {
+        if clock.flagged(team_white, started.elapsed()) {
+            //Off whatever was half typed
+            if terminal {
+                println!();
+            }
+            return None;
+        }
+        //Tenths of a second are shown in the last ten seconds
+        let remaining = clock.remaining_after(team_white, started.elapsed());
+        let wait = remaining.min(if remaining.as_secs() < 10 {
+            std::time::Duration::from_millis(100)
+        } else {
+            std::time::Duration::from_secs(1)
+        });
+        match lines.recv_timeout(wait) {
+            Ok(v) => return Some(v),
+            Err(RecvTimeoutError::Disconnected) => std::process::exit(0),
+            //Save the cursor, rewrite the clock line above it and put the cursor back
+            Err(RecvTimeoutError::Timeout) if terminal => {
+                print!("\x1b7\x1b[1A\r{}\x1b[K\x1b8", line());
+                std::io::stdout().flush().unwrap();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+}
+
+//Err holds a command typed in place of a location
+fn take_position_input(
+    position: &position::Position,
+    should_contain_piece: bool,
+    clock: Option<(&clock::Clock, std::time::Instant)>,
+) -> Result<(u8, u8), Command> {
+    //Only the board's own columns and rows, e.g. [A-H][1-8]
+    let square = format!(
+        "[A-{}][1-{}]",
+        (b'A' + position.width - 1) as char,
+        position.height
+    );
+    let proper_format_regex = regex::Regex::new(&format!("^{square}$")).unwrap();
+    let drop_regex = regex::Regex::new(&format!("^([PNBRQ]?)@({square})$")).unwrap();
+
+    loop {
+        let Some(input) = read_move_input(position, clock) else {
+            break Err(vec![FLAG.to_string()]);
+        };
+        let piece_to_move = input.to_uppercase();
+
+        //Only the command name is uppercased, file names keep their case
+        let words = input
+            .split_whitespace()
+            .enumerate()
+            .map(|(i, x)| if i == 0 { x.to_uppercase() } else { x.to_string() })
+            .collect::<Command>();
+        if words
+            .first()
+            .is_some_and(|x| COMMANDS.contains(&x.as_str()))
+        {
+            break Err(words);
+        }
+
+        if let Some(v) = drop_regex.captures(piece_to_move.trim()) {
+            if should_contain_piece {
+                let piece = if v[1].is_empty() { "P" } else { &v[1] };
+                break Err(vec![DROP.to_string(), piece.to_string(), v[2].to_string()]);
+            }
+            println!("Only the piece to move can be dropped. Please try again... {FORMAT_LABEL}");
+        } else if proper_format_regex.is_match(piece_to_move.trim()) {
+            let location = parse_location(piece_to_move.trim(), position.top());
+            if position
+                .pieces
+                .iter()
+                .map(|x| x.position)
+                .collect::<Vec<(u8, u8)>>()
+                .contains(&location)
+                || !should_contain_piece
+            {
+                break Ok(location);
+            } else {
+                println!("No piece in that location. Please try again... {FORMAT_LABEL}");
+            }
+        } else {
+            println!("Incorrect format. Please try again... {FORMAT_LABEL}");
+        }
+    }
+}
+
+fn take_move_input(
+    position: &position::Position,
+    legal_moves: &[movegen::Move],
+    team_white: bool,
+    clock: Option<(&clock::Clock, std::time::Instant)>,
+) -> Result<movegen::Move, Command> {
+    println!("Choose piece to move... {FORMAT_LABEL}");
+    let piece_to_move = loop {
+        let location = match take_position_input(position, true, clock) {
+            Ok(v) => v,
+            //A drop is a whole move by itself
+            Err(command) if command[0] == DROP => {
+                let piece_type = command[1].chars().next().and_then(position::piece_from_letter);
+                let to = parse_location(&command[2], position.top());
+                match legal_moves
+                    .iter()
+                    .find(|x| x.drop.is_some() && x.drop == piece_type && x.to == to)
+                {
+                    Some(v) => return Ok(*v),
+                    None => {
+                        println!(
+                            "Cannot drop that there. Choose piece to move again... {FORMAT_LABEL}"
+                        );
+                        continue;
+                    }
                 }
             }
+            Err(command) => return Err(command),
         };
 
-        if let Some(v) = current_pieces
+        match position.piece_at(location) {
+            Some(v) if v.team_white == team_white => break location,
+            Some(_) => println!("Not your turn! Choose piece to move again... {FORMAT_LABEL}"),
+            None => println!("No piece in that position. Choose again... {FORMAT_LABEL}"),
+        }
+    };
+
+    println!("Choose where to move... {FORMAT_LABEL}");
+    let mut where_to_move = take_position_input(position, false, clock)?;
+
+    let options = loop {
+        let options = legal_moves
             .iter()
-            .position(|x| x.position == where_to_move)
-        {
-            fallen_pieces.push(current_pieces.remove(v));
+            .filter(|x| x.from == piece_to_move && x.to == where_to_move)
+            .copied()
+            .collect::<Vec<movegen::Move>>();
+
+        if !options.is_empty() {
+            break options;
         }
 
-        current_pieces.push(new_piece);
-        
-        turn += 1;
+        println!("Cannot move piece there. Choose where to move again... {FORMAT_LABEL}");
+        where_to_move = take_position_input(position, false, clock)?;
+    };
+
+    //Several options for the same squares only happen when a pawn promotes
+    if options.len() == 1 {
+        return Ok(options[0]);
     }
+
+    //Antichess lets pawns become kings as well
+    let letters = options
+        .iter()
+        .filter_map(|x| x.promotion)
+        .map(|x| position::piece_letter(x).to_ascii_uppercase().to_string())
+        .collect::<Vec<String>>();
+    let choices = format!(
+        "({} or {})",
+        letters[..letters.len() - 1].join(", "),
+        letters[letters.len() - 1]
+    );
+
+    println!("Choose what to promote to... {choices}");
+    loop {
+        let Some(promotion) = read_move_input(position, clock) else {
+            return Err(vec![FLAG.to_string()]);
+        };
+
+        if let Some(v) = options.iter().find(|x| {
+            x.promotion
+                .is_some_and(|x| position::piece_letter(x).to_string() == promotion.trim().to_lowercase())
+        }) {
+            break Ok(*v);
+        }
+
+        println!("Incorrect piece. Please try again... {choices}");
+    }
+}
+
+//Move in the notation of the board labels, e.g. E7-E5 or N@F3
+fn format_move(mv: movegen::Move, top: u8) -> String {
+    if let Some(piece_type) = mv.drop {
+        return format!(
+            "{}@{}",
+            position::piece_letter(piece_type).to_ascii_uppercase(),
+            format_location(mv.to, top)
+        );
+    }
+    format!(
+        "{}-{}{}",
+        format_location(mv.from, top),
+        format_location(mv.to, top),
+        mv.promotion.map_or(String::new(), |x| format!(
+            "={}",
+            position::piece_letter(x).to_ascii_uppercase()
+        ))
+    )
 }
 
-fn parse_location(location: &str) -> (u8, u8) {
+fn describe_move(mv: movegen::Move, top: u8) -> String {
+    if let Some(piece_type) = mv.drop {
+        return format!(
+            "{} from hand to {}",
+            piece_name(piece_type),
+            format_location(mv.to, top)
+        );
+    }
+    format!(
+        "{} to {}{}",
+        format_location(mv.from, top),
+        format_location(mv.to, top),
+        mv.promotion
+            .map_or(String::new(), |x| format!(" promoting to {}", piece_name(x)))
+    )
+}
+
+fn piece_name(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "pawn",
+        PieceType::Rook => "rook",
+        PieceType::Knight => "knight",
+        PieceType::Bishop => "bishop",
+        PieceType::King => "king",
+        PieceType::Queen => "queen",
+        PieceType::Fairy(i) => &fairy::get(i).name,
+    }
+}
+
+//Labels count rows from the top of the board, which is further down on boards with fewer rows
+fn parse_location(location: &str, top: u8) -> (u8, u8) {
     let chars = location.chars().collect::<Vec<char>>();
-    
-    let column = "ABCDEFGH".chars().position(|x| x == chars[0]).unwrap();
+
+    let column = chars[0] as u8 - b'A';
     let row = chars[1].to_string().parse::<u8>().unwrap() - 1;
 
-    (column as u8, row)
+    (column, row + top)
+}
+
+fn format_location(location: (u8, u8), top: u8) -> String {
+    format!("{}{}", (b'A' + location.0) as char, location.1 - top + 1)
 }
 
 fn default_pieces() -> Vec<Piece> {
@@ -527,90 +1371,103 @@ fn default_pieces() -> Vec<Piece> {
     pieces
 }
 
-fn render_board(pieces: Vec<Piece>) -> String {
-    let row_label = "   A B C D E F G H\n".to_string();
-
-    let mut board = "󰝤 󰝤 󰝤 󰝤 \n 󰝤 󰝤 󰝤 󰝤\n".repeat(4);
+fn piece_glyph(piece_type: PieceType, team_white: bool) -> char {
+    match piece_type {
+        PieceType::Pawn => {
+            if team_white {
+                ''
+            } else {
+                ''
+            }
+        }
 
-    for piece in pieces {
-        let row = board.lines().collect::<Vec<&str>>()[piece.position.1 as usize];
-        let mut row_chars = row.chars().collect::<Vec<char>>();
-        row_chars[piece.position.0 as usize] = match piece.piece_type {
-            PieceType::Pawn => {
-                if piece.team_white {
-                    ''
-                } else {
-                    ''
-                }
+        PieceType::Rook => {
+            if team_white {
+                '󰡛'
+            } else {
+                ''
             }
+        }
 
-            PieceType::Rook => {
-                if piece.team_white {
-                    '󰡛'
-                } else {
-                    ''
-                }
+        PieceType::Knight => {
+            if team_white {
+                ''
+            } else {
+                ''
             }
+        }
 
-            PieceType::Knight => {
-                if piece.team_white {
-                    ''
-                } else {
-                    ''
-                }
+        PieceType::Bishop => {
+            if team_white {
+                '󰡜'
+            } else {
+                ''
             }
+        }
 
-            PieceType::Bishop => {
-                if piece.team_white {
-                    '󰡜'
-                } else {
-                    ''
-                }
+        PieceType::Queen => {
+            if team_white {
+                '󰡚'
+            } else {
+                ''
             }
+        }
 
-            PieceType::Queen => {
-                if piece.team_white {
-                    '󰡚'
-                } else {
-                    ''
-                }
+        PieceType::King => {
+            if team_white {
+                '󰡗'
+            } else {
+                ''
             }
+        }
 
-            PieceType::King => {
-                if piece.team_white {
-                    '󰡗'
-                } else {
-                    ''
-                }
+        PieceType::Fairy(i) => {
+            let glyphs = fairy::get(i).glyphs;
+            if team_white {
+                glyphs.0
+            } else {
+                glyphs.1
             }
-        };
+        }
+    }
+}
 
-        let row = &row_chars.iter().collect::<String>();
+fn render_board(position: &position::Position) -> String {
+    let columns = (0..position.width)
+        .map(|x| ((b'A' + x) as char).to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let row_label = format!("   {columns}\n");
 
-        board = board
-            .lines()
-            .enumerate()
-            .map(|(i, v)| {
-                if i == piece.position.1 as usize {
-                    row.to_string() + "\n"
-                } else {
-                    v.to_string() + "\n"
-                }
-            })
-            .collect::<String>();
+    //Squares keep the colours they have on the usual board whatever its size
+    let top = position.top();
+    let mut board = (top..position::MAX_HEIGHT)
+        .map(|y| {
+            (0..position.width)
+                .map(|x| if (x + y) % 2 == 0 { '󰝤' } else { ' ' })
+                .collect::<Vec<char>>()
+        })
+        .collect::<Vec<Vec<char>>>();
+
+    for piece in &position.pieces {
+        board[(piece.position.1 - top) as usize][piece.position.0 as usize] =
+            piece_glyph(piece.piece_type, piece.team_white);
     }
 
     row_label
         + &board
-            .lines()
+            .iter()
             .enumerate()
             //number the rows and add spacing
             .map(|(i, v)| {
-                format!("{}{}", i + 1, v)
-                    .split("")
-                    .collect::<Vec<&str>>()
-                    .join(" ")
-                    + "\n"
+                format!(
+                    " {} {} \n",
+                    i + 1,
+                    v.iter()
+                        .map(char::to_string)
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                )
             })
             .collect::<String>()
 }
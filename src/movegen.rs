@@ -0,0 +1,891 @@
+use crate::bitboard::{
+    bishop_attacks, bit, index_square, king_attacks, knight_attacks, pawn_attacks, rook_attacks,
+    square_index, squares, Bitboards, FILES,
+};
+use crate::fairy;
+use crate::position::{
+    castling_index, home_row, king_side, parse_square, piece_from_letter, piece_letter,
+    square_name, Position, BLACK_KING_SIDE, BLACK_QUEEN_SIDE, WHITE_KING_SIDE, WHITE_QUEEN_SIDE,
+};
+use crate::variant::{self, Variant};
+use crate::zobrist;
+use crate::{Piece, PieceType};
+
+/*
+Castling is written as the king moving two squares, or taking its own rook in Chess960. A
+crazyhouse drop puts a piece from the pocket on the board, with from and to the same square.
+*/
+#[derive(Clone, Copy, PartialEq)]
+pub struct Move {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    pub promotion: Option<PieceType>,
+    pub drop: Option<PieceType>,
+}
+
+impl Move {
+    //Long algebraic notation as used by UCI, e.g. e2e4, e7e8q or N@f3
+    pub fn to_uci(self) -> String {
+        if let Some(piece_type) = self.drop {
+            return format!(
+                "{}@{}",
+                piece_letter(piece_type).to_ascii_uppercase(),
+                square_name(self.to)
+            );
+        }
+        format!(
+            "{}{}{}",
+            square_name(self.from),
+            square_name(self.to),
+            self.promotion
+                .map_or(String::new(), |x| piece_letter(x).to_string())
+        )
+    }
+
+    pub fn from_uci(position: &Position, text: &str) -> Option<Move> {
+        legal_moves(position)
+            .into_iter()
+            .find(|x| x.to_uci().eq_ignore_ascii_case(text))
+    }
+
+    //Standard algebraic notation as in PGN, e.g. Nbd7, exd6, O-O or e8=Q+
+    pub fn from_san(position: &Position, text: &str) -> Option<Move> {
+        let text = text.trim_end_matches(['+', '#', '!', '?']);
+        let moves = legal_moves(position);
+        let castles = |king_side: bool| {
+            let right = castling_index(position.white_to_move, king_side);
+            moves
+                .iter()
+                .copied()
+                .find(|&x| castling_right(position, x) == Some(right))
+        };
+
+        match text {
+            "O-O" | "0-0" => return castles(true),
+            "O-O-O" | "0-0-0" => return castles(false),
+            _ => {}
+        }
+
+        //Drops name the piece and the square, pawns sometimes without their letter
+        if let Some((piece, square)) = text.split_once('@') {
+            let piece_type = match piece.chars().next() {
+                Some(c) => piece_from_letter(c)?,
+                None => PieceType::Pawn,
+            };
+            let to = parse_square(square)?;
+            return moves
+                .into_iter()
+                .find(|x| x.drop == Some(piece_type) && x.to == to);
+        }
+
+        let (text, promotion) = match text.rsplit_once('=') {
+            Some((text, piece)) => (text, Some(piece)),
+            //Some files leave out the equals sign, as in e8Q
+            None if text.ends_with(['N', 'B', 'R', 'Q']) => {
+                (&text[..text.len() - 1], Some(&text[text.len() - 1..]))
+            }
+            None => (text, None),
+        };
+        let promotion = match promotion {
+            Some(v) => Some(
+                piece_from_letter(v.chars().next()?)
+                    .filter(|&x| x != PieceType::Pawn && x != PieceType::King)?,
+            ),
+            None => None,
+        };
+
+        let piece_type = match text.chars().next()? {
+            c if c.is_ascii_uppercase() => piece_from_letter(c)?,
+            _ => PieceType::Pawn,
+        };
+        let rest = text
+            .trim_start_matches(|x: char| x.is_ascii_uppercase())
+            .replace(['x', '-', ':'], "");
+        if rest.len() < 2 {
+            return None;
+        }
+        let to = parse_square(&rest[rest.len() - 2..])?;
+
+        //Whatever comes before the destination tells apart pieces that could both go there
+        let hint = &rest[..rest.len() - 2];
+        let file = hint.chars().find(|x| ('a'..='h').contains(x));
+        let rank = hint.chars().find(|x| x.is_ascii_digit());
+
+        let mut matching = moves.into_iter().filter(|x| {
+            x.to == to
+                && x.promotion == promotion
+                && position
+                    .piece_at(x.from)
+                    .is_some_and(|x| x.piece_type == piece_type)
+                && file.is_none_or(|v| square_name(x.from).starts_with(v))
+                && rank.is_none_or(|v| square_name(x.from).ends_with(v))
+        });
+
+        let mv = matching.next()?;
+        matching.next().is_none().then_some(mv)
+    }
+
+    //The move in standard algebraic notation, the position being the one it's played from
+    pub fn to_san(self, position: &Position) -> String {
+        let piece_type = position
+            .piece_at(self.from)
+            .map_or(PieceType::Pawn, |x| x.piece_type);
+        let capture = position.piece_at(self.to).is_some()
+            || (piece_type == PieceType::Pawn && self.from.0 != self.to.0);
+        let from = square_name(self.from);
+        let takes = if capture { "x" } else { "" };
+
+        let mut san = if self.drop.is_some() {
+            self.to_uci()
+        } else if let Some(right) = castling_right(position, self) {
+            if king_side(right) { "O-O" } else { "O-O-O" }.to_string()
+        } else if piece_type == PieceType::Pawn {
+            format!(
+                "{}{takes}{}{}",
+                if capture { &from[..1] } else { "" },
+                square_name(self.to),
+                self.promotion.map_or(String::new(), |x| format!(
+                    "={}",
+                    piece_letter(x).to_ascii_uppercase()
+                ))
+            )
+        } else {
+            //Other pieces of the same kind that could go to the same square
+            let rivals = legal_moves(position)
+                .into_iter()
+                .filter(|x| {
+                    x.to == self.to
+                        && x.from != self.from
+                        && position
+                            .piece_at(x.from)
+                            .is_some_and(|x| x.piece_type == piece_type)
+                })
+                .collect::<Vec<Move>>();
+            let disambiguation = if rivals.is_empty() {
+                ""
+            } else if rivals.iter().all(|x| x.from.0 != self.from.0) {
+                &from[..1]
+            } else if rivals.iter().all(|x| x.from.1 != self.from.1) {
+                &from[1..]
+            } else {
+                &from
+            };
+
+            format!(
+                "{}{disambiguation}{takes}{}",
+                piece_letter(piece_type).to_ascii_uppercase(),
+                square_name(self.to)
+            )
+        };
+
+        let mut after = position.clone();
+        make_move(&mut after, self);
+        if in_check(&after) {
+            san.push(if legal_moves(&after).is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+}
+
+const PROMOTIONS: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+pub fn in_check(position: &Position) -> bool {
+    position
+        .variant
+        .king_attacked(&position.board, position.white_to_move)
+}
+
+//What a move changes beyond the pieces it moves, kept so the move can be taken back
+pub struct Undo {
+    pub captured: Option<Piece>,
+    //Pieces an atomic capture blew up, the one that took included
+    pub exploded: Vec<Piece>,
+    castled: Option<usize>,
+    castling: [bool; 4],
+    checks: [u8; 2],
+    pockets: [[u8; 5]; 2],
+    promoted: u128,
+    en_passant: Option<(u8, u8)>,
+    halfmove_clock: u32,
+    hash: u64,
+    board: Bitboards,
+}
+
+//The castling right a move uses, None when it isn't castling
+pub fn castling_right(position: &Position, mv: Move) -> Option<usize> {
+    let board = &position.board;
+    let (team_white, piece_type) = board.piece_at(square_index(mv.from))?;
+    if piece_type != PieceType::King
+        || mv.from.1 != home_row(team_white, position.height)
+        || mv.to.1 != mv.from.1
+    {
+        return None;
+    }
+
+    //The king goes further than two squares on boards wider than eight
+    let castles = if position.chess960 {
+        board.piece_at(square_index(mv.to)) == Some((team_white, PieceType::Rook))
+    } else {
+        mv.to.0.abs_diff(mv.from.0) >= 2
+    };
+    castles.then_some(castling_index(team_white, mv.to.0 > mv.from.0))
+}
+
+/*
+Where the king and rook end up whatever they started on, on the g and f files or the c and d files
+of the usual board. Wider boards keep the king side ones next to the last file the same way.
+*/
+fn castled_squares(right: usize, row: u8, width: u8) -> ((u8, u8), (u8, u8)) {
+    if king_side(right) {
+        ((width - 2, row), (width - 3, row))
+    } else {
+        ((2, row), (3, row))
+    }
+}
+
+//Plays the move on the position itself, returning what unmake_move needs to take it back
+pub fn make_move(position: &mut Position, mv: Move) -> Undo {
+    let team_white = position.white_to_move;
+    let castled = castling_right(position, mv);
+    let castling_rooks = position.castling_rooks;
+    let undo = Undo {
+        captured: None,
+        exploded: vec![],
+        castled,
+        castling: position.castling,
+        checks: position.checks,
+        pockets: position.pockets,
+        promoted: position.promoted,
+        en_passant: position.en_passant,
+        halfmove_clock: position.halfmove_clock,
+        hash: position.hash,
+        board: position.board,
+    };
+
+    let moving = mv.drop.unwrap_or_else(|| {
+        position
+            .board
+            .piece_at(square_index(mv.from))
+            .map_or(PieceType::Pawn, |x| x.1)
+    });
+
+    //A pawn moving diagonally onto the en passant square strikes the pawn beside it
+    let struck_square = if moving == PieceType::Pawn
+        && Some(mv.to) == position.en_passant
+        && mv.from.0 != mv.to.0
+    {
+        (mv.to.0, mv.from.1)
+    } else {
+        mv.to
+    };
+
+    //The hash is updated with only what the move changes
+    let mut hash = position.hash
+        ^ zobrist::en_passant_key(position)
+        ^ zobrist::castling_keys(&position.castling)
+        ^ zobrist::turn_key(true);
+    let board = &mut position.board;
+    let pieces = &mut position.pieces;
+
+    //A Chess960 king lands on its own rook's square without taking it
+    let captured = pieces
+        .iter()
+        .position(|x| x.position == struck_square)
+        .filter(|_| castled.is_none())
+        .map(|i| pieces.swap_remove(i));
+    if let Some(piece) = &captured {
+        hash ^= zobrist::piece_key(piece);
+        board.toggle(
+            piece.team_white,
+            piece.piece_type,
+            square_index(piece.position),
+        );
+    }
+
+    //Castling moves the rook over the king, both found first as either may land where the other was
+    if let Some(right) = castled {
+        let rook_from = (castling_rooks[right], mv.from.1);
+        let (king_to, rook_to) = castled_squares(right, mv.from.1, position.width);
+        let king = pieces.iter().position(|x| x.position == mv.from);
+        let rook = pieces.iter().position(|x| x.position == rook_from);
+
+        for (index, to) in [(king, king_to), (rook, rook_to)] {
+            if let Some(piece) = index.map(|i| &mut pieces[i]) {
+                hash ^= zobrist::piece_key(piece);
+                board.toggle(team_white, piece.piece_type, square_index(piece.position));
+                piece.position = to;
+                hash ^= zobrist::piece_key(piece);
+                board.toggle(team_white, piece.piece_type, square_index(piece.position));
+            }
+        }
+    } else if let Some(piece_type) = mv.drop {
+        let piece = Piece {
+            team_white,
+            piece_type,
+            position: mv.to,
+        };
+        hash ^= zobrist::piece_key(&piece);
+        board.toggle(team_white, piece_type, square_index(mv.to));
+        pieces.push(piece);
+    } else if let Some(piece) = pieces.iter_mut().find(|x| x.position == mv.from) {
+        hash ^= zobrist::piece_key(piece);
+        board.toggle(team_white, piece.piece_type, square_index(piece.position));
+        piece.position = mv.to;
+        if let Some(promotion) = mv.promotion {
+            piece.piece_type = promotion;
+        }
+        hash ^= zobrist::piece_key(piece);
+        board.toggle(team_white, piece.piece_type, square_index(piece.position));
+    }
+
+    //Pieces a capture blows up go the same way as the one taken
+    let mut exploded = vec![];
+    let area = match captured {
+        Some(_) => position
+            .variant
+            .explosion(&position.board, square_index(mv.to)),
+        None => 0,
+    };
+    if area != 0 {
+        let pieces = &mut position.pieces;
+        let mut i = 0;
+        while i < pieces.len() {
+            if area & bit(pieces[i].position) != 0 {
+                let piece = pieces.swap_remove(i);
+                hash ^= zobrist::piece_key(&piece);
+                position.board.toggle(
+                    piece.team_white,
+                    piece.piece_type,
+                    square_index(piece.position),
+                );
+                exploded.push(piece);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    hash ^= variant::fill_pockets(position, mv, captured.as_ref(), struck_square);
+
+    //Moving the king or a rook, or losing a rook, gives up castling on that side
+    let castling = &mut position.castling;
+    for piece in &exploded {
+        for right in [
+            WHITE_KING_SIDE,
+            WHITE_QUEEN_SIDE,
+            BLACK_KING_SIDE,
+            BLACK_QUEEN_SIDE,
+        ] {
+            let square = (
+                castling_rooks[right],
+                home_row(right < BLACK_KING_SIDE, position.height),
+            );
+            if piece.position == square
+                || (piece.piece_type == PieceType::King
+                    && piece.team_white == (right < BLACK_KING_SIDE))
+            {
+                castling[right] = false;
+            }
+        }
+    }
+    for right in [
+        WHITE_KING_SIDE,
+        WHITE_QUEEN_SIDE,
+        BLACK_KING_SIDE,
+        BLACK_QUEEN_SIDE,
+    ] {
+        let square = (
+            castling_rooks[right],
+            home_row(right < BLACK_KING_SIDE, position.height),
+        );
+        if mv.from == square || mv.to == square {
+            castling[right] = false;
+        }
+    }
+    if moving == PieceType::King {
+        let rights = if team_white {
+            [WHITE_KING_SIDE, WHITE_QUEEN_SIDE]
+        } else {
+            [BLACK_KING_SIDE, BLACK_QUEEN_SIDE]
+        };
+        for right in rights {
+            castling[right] = false;
+        }
+    }
+
+    position.en_passant = if moving == PieceType::Pawn && mv.to.1.abs_diff(mv.from.1) == 2 {
+        Some((mv.from.0, (mv.from.1 + mv.to.1) / 2))
+    } else {
+        None
+    };
+    position.halfmove_clock = if moving == PieceType::Pawn || captured.is_some() {
+        0
+    } else {
+        position.halfmove_clock + 1
+    };
+    if !team_white {
+        position.fullmove_number += 1;
+    }
+    position.white_to_move = !team_white;
+
+    position.hash = hash ^ zobrist::castling_keys(&position.castling);
+    position.hash ^= zobrist::en_passant_key(position);
+
+    if position.variant == Variant::ThreeCheck && in_check(position) {
+        position.hash ^= zobrist::checks_key(&position.checks);
+        position.checks[if team_white { 0 } else { 1 }] += 1;
+        position.hash ^= zobrist::checks_key(&position.checks);
+    }
+
+    Undo {
+        captured,
+        exploded,
+        ..undo
+    }
+}
+
+pub fn unmake_move(position: &mut Position, mv: Move, undo: Undo) {
+    let team_white = !position.white_to_move;
+    let pieces = &mut position.pieces;
+    //The piece that took goes back to where it was like any other
+    pieces.extend(undo.exploded);
+
+    if let Some(right) = undo.castled {
+        let rook_from = (position.castling_rooks[right], mv.from.1);
+        let (king_to, rook_to) = castled_squares(right, mv.from.1, position.width);
+        let find = |square: (u8, u8), piece_type: PieceType| {
+            pieces
+                .iter()
+                .position(|x| x.position == square && x.piece_type == piece_type)
+        };
+        let king = find(king_to, PieceType::King);
+        let rook = find(rook_to, PieceType::Rook);
+
+        for (index, from) in [(king, mv.from), (rook, rook_from)] {
+            if let Some(i) = index {
+                pieces[i].position = from;
+            }
+        }
+    } else if mv.drop.is_some() {
+        pieces.retain(|x| x.position != mv.to);
+    } else if let Some(piece) = pieces.iter_mut().find(|x| x.position == mv.to) {
+        piece.position = mv.from;
+        if mv.promotion.is_some() {
+            piece.piece_type = PieceType::Pawn;
+        }
+    }
+    pieces.extend(undo.captured);
+
+    if !team_white {
+        position.fullmove_number -= 1;
+    }
+    position.white_to_move = team_white;
+    position.castling = undo.castling;
+    position.checks = undo.checks;
+    position.pockets = undo.pockets;
+    position.promoted = undo.promoted;
+    position.en_passant = undo.en_passant;
+    position.halfmove_clock = undo.halfmove_clock;
+    position.hash = undo.hash;
+    position.board = undo.board;
+}
+
+/*
+The king and rook may start anywhere in Chess960. Every square either passes over has to be empty
+but for the two of them, and the king can't pass through or land on an attacked square.
+*/
+fn castling_moves(position: &Position) -> Vec<Move> {
+    let board = &position.board;
+    let team_white = position.white_to_move;
+    let row = home_row(team_white, position.height);
+    let Some(king) = squares(board.pieces(team_white, PieceType::King))
+        .map(index_square)
+        .find(|x| x.1 == row)
+    else {
+        return vec![];
+    };
+
+    //Outside Chess960 castling from elsewhere would read as a plain king move
+    if (!position.chess960 && king.0 != position.width / 2)
+        || !position.variant.royal_king()
+        || board.is_attacked(square_index(king), !team_white)
+    {
+        return vec![];
+    }
+
+    let span = |a: u8, b: u8| a.min(b)..=a.max(b);
+    [true, false]
+        .into_iter()
+        .filter_map(|king_side| {
+            let right = castling_index(team_white, king_side);
+            let rook = (position.castling_rooks[right], row);
+            if !position.castling[right]
+                || board.pieces(team_white, PieceType::Rook) & bit(rook) == 0
+            {
+                return None;
+            }
+
+            //Both leave the board first, as they may stand in each other's way
+            let (king_to, rook_to) = castled_squares(right, row, position.width);
+            let mut without = *board;
+            without.toggle(team_white, PieceType::King, square_index(king));
+            without.toggle(team_white, PieceType::Rook, square_index(rook));
+
+            let clear = span(king.0, king_to.0)
+                .chain(span(rook.0, rook_to.0))
+                .all(|x| without.occupied() & bit((x, row)) == 0);
+            let safe = span(king.0, king_to.0)
+                .all(|x| !without.is_attacked(square_index((x, row)), !team_white));
+
+            (clear && safe).then_some(Move {
+                from: king,
+                to: if position.chess960 { rook } else { king_to },
+                promotion: None,
+                drop: None,
+            })
+        })
+        .collect()
+}
+
+fn pseudo_legal_moves(position: &Position) -> Vec<Move> {
+    let board = &position.board;
+    let team_white = position.white_to_move;
+    let own = board.team(team_white);
+    let enemy = board.team(!team_white);
+    let occupied = own | enemy;
+    let area = position.area();
+    let mut moves = castling_moves(position);
+
+    for from in squares(own) {
+        let Some((_, piece_type)) = board.piece_at(from) else {
+            continue;
+        };
+
+        let targets = match piece_type {
+            PieceType::Pawn => {
+                let forward = |square: usize| {
+                    if team_white {
+                        square + FILES
+                    } else {
+                        square - FILES
+                    }
+                };
+                let double_step =
+                    position
+                        .variant
+                        .double_step(team_white, from / FILES, position.height);
+
+                let mut pushes = 0;
+                if occupied & 1 << forward(from) == 0 {
+                    pushes |= 1 << forward(from);
+                    if double_step && occupied & 1 << forward(forward(from)) == 0 {
+                        pushes |= 1 << forward(forward(from));
+                    }
+                }
+
+                pushes
+                    | pawn_attacks(team_white, from) & (enemy | position.en_passant.map_or(0, bit))
+            }
+            PieceType::Knight => knight_attacks(from),
+            PieceType::Bishop => bishop_attacks(from, occupied),
+            PieceType::Rook => rook_attacks(from, occupied),
+            PieceType::Queen => bishop_attacks(from, occupied) | rook_attacks(from, occupied),
+            PieceType::Fairy(i) => fairy::attacks(i, from, occupied),
+            PieceType::King if !position.variant.king_takes() => king_attacks(from) & !enemy,
+            //King safety is left to the check test in legal_moves
+            PieceType::King => king_attacks(from),
+        } & !own
+            & area;
+
+        for to in squares(targets) {
+            let (from, to) = (index_square(from), index_square(to));
+
+            if piece_type == PieceType::Pawn && to.1 == home_row(!team_white, position.height) {
+                let king = (!position.variant.royal_king()).then_some(PieceType::King);
+                let fairy = (0..fairy::pieces().len()).map(|i| PieceType::Fairy(i as u8));
+                for promotion in PROMOTIONS
+                    .into_iter()
+                    .chain(fairy)
+                    .filter(|&x| position.variant.promotes_to(x))
+                    .chain(king)
+                {
+                    moves.push(Move {
+                        from,
+                        to,
+                        promotion: Some(promotion),
+                        drop: None,
+                    });
+                }
+            } else {
+                moves.push(Move {
+                    from,
+                    to,
+                    promotion: None,
+                    drop: None,
+                });
+            }
+        }
+    }
+
+    moves.extend(variant::drop_moves(position));
+    moves
+}
+
+//Plays the move on the bitboards alone, which is all the check test needs
+fn leaves_king_safe(position: &Position, mv: Move) -> bool {
+    let mut board = position.board;
+    let team_white = position.white_to_move;
+    let from = square_index(mv.from);
+
+    //A dropped piece can only ever block a check
+    if let Some(piece_type) = mv.drop {
+        board.toggle(team_white, piece_type, square_index(mv.to));
+        return !position.variant.king_attacked(&board, team_white);
+    }
+
+    let Some((_, piece_type)) = board.piece_at(from) else {
+        return false;
+    };
+
+    if let Some(right) = castling_right(position, mv) {
+        let (king_to, rook_to) = castled_squares(right, mv.from.1, position.width);
+        let rook_from = square_index((position.castling_rooks[right], mv.from.1));
+        board.toggle(team_white, PieceType::King, from);
+        board.toggle(team_white, PieceType::King, square_index(king_to));
+        board.toggle(team_white, PieceType::Rook, rook_from);
+        board.toggle(team_white, PieceType::Rook, square_index(rook_to));
+        return !position.variant.king_attacked(&board, team_white);
+    }
+
+    let struck = if piece_type == PieceType::Pawn
+        && Some(mv.to) == position.en_passant
+        && mv.from.0 != mv.to.0
+    {
+        square_index((mv.to.0, mv.from.1))
+    } else {
+        square_index(mv.to)
+    };
+
+    let captured = board.piece_at(struck);
+    if let Some((white, kind)) = captured {
+        board.toggle(white, kind, struck);
+    }
+    board.toggle(team_white, piece_type, from);
+    board.toggle(team_white, piece_type, square_index(mv.to));
+
+    if captured.is_some() {
+        let area = position.variant.explosion(&board, square_index(mv.to));
+        for square in squares(area) {
+            if let Some((white, kind)) = board.piece_at(square) {
+                board.toggle(white, kind, square);
+            }
+        }
+    }
+
+    !position.variant.king_attacked(&board, team_white)
+}
+
+pub fn legal_moves(position: &Position) -> Vec<Move> {
+    let moves = pseudo_legal_moves(position);
+    //Without a royal king nothing has to be kept safe
+    let moves = if position.variant.royal_king() {
+        moves
+            .into_iter()
+            .filter(|&mv| leaves_king_safe(position, mv))
+            .collect()
+    } else {
+        moves
+    };
+    variant::restrict_moves(position, moves)
+}
+
+//Counts leaf positions to check the move generator against known totals
+pub fn perft(position: &mut Position, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = legal_moves(position);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    moves
+        .into_iter()
+        .map(|mv| {
+            let undo = make_move(position, mv);
+            let count = perft(position, depth - 1);
+            unmake_move(position, mv, undo);
+            count
+        })
+        .sum()
+}
+
+pub enum Outcome {
+    WhiteWins(&'static str),
+    BlackWins(&'static str),
+    Draw(&'static str),
+}
+
+impl Outcome {
+    //Result as written in PGN and engine protocols
+    pub fn result(&self) -> &'static str {
+        match self {
+            Outcome::WhiteWins(_) => "1-0",
+            Outcome::BlackWins(_) => "0-1",
+            Outcome::Draw(_) => "1/2-1/2",
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Outcome::WhiteWins(v) | Outcome::BlackWins(v) | Outcome::Draw(v) => v,
+        }
+    }
+}
+
+//Neither side has enough left to ever checkmate, so the position is dead
+pub fn insufficient_material(pieces: &[Piece]) -> bool {
+    let others = pieces
+        .iter()
+        .filter(|x| x.piece_type != PieceType::King)
+        .collect::<Vec<&Piece>>();
+
+    match others.as_slice() {
+        [] => true,
+        [piece] => [PieceType::Knight, PieceType::Bishop].contains(&piece.piece_type),
+        //Bishops that all stay on one colour of square can't mate either
+        _ => {
+            others.iter().all(|x| x.piece_type == PieceType::Bishop)
+                && others.iter().all(|x| {
+                    (x.position.0 + x.position.1) % 2
+                        == (others[0].position.0 + others[0].position.1) % 2
+                })
+        }
+    }
+}
+
+//Whether some series of legal moves could still let the team checkmate
+pub fn can_mate(pieces: &[Piece], team_white: bool) -> bool {
+    pieces
+        .iter()
+        .any(|x| x.team_white == team_white && x.piece_type != PieceType::King)
+        && !insufficient_material(pieces)
+}
+
+//Result when a side runs out of time, a draw if its opponent could never have won anyway
+pub fn timeout(position: &Position, flagged_white: bool) -> Outcome {
+    if position.variant.material_draws() && !can_mate(&position.pieces, !flagged_white) {
+        Outcome::Draw("Timeout vs insufficient material")
+    } else if flagged_white {
+        Outcome::BlackWins("Time forfeit")
+    } else {
+        Outcome::WhiteWins("Time forfeit")
+    }
+}
+
+//None while the game goes on
+pub fn outcome(position: &Position) -> Option<Outcome> {
+    if let Some(outcome) = variant::outcome(position) {
+        Some(outcome)
+    } else if legal_moves(position).is_empty() {
+        Some(variant::no_moves(position))
+    } else if position.halfmove_clock >= 100 {
+        Some(Outcome::Draw("Fifty move rule"))
+    } else if position.variant.material_draws() && insufficient_material(&position.pieces) {
+        Some(Outcome::Draw("Insufficient material"))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::perft;
+    use crate::position::Position;
+    use crate::variant::Variant;
+
+    //Counts for each depth from 1, the published ones where there are any
+    fn check(mut position: Position, counts: &[u64]) {
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(
+                perft(&mut position, depth as u8 + 1),
+                count,
+                "depth {}",
+                depth + 1
+            );
+        }
+    }
+
+    fn check_fen(fen: &str, counts: &[u64]) {
+        check(Position::from_fen(fen).unwrap(), counts);
+    }
+
+    #[test]
+    fn startpos() {
+        check(Position::startpos(), &[20, 400, 8902]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        check_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            &[48, 2039, 97862],
+        );
+    }
+
+    #[test]
+    fn endgame_with_en_passant() {
+        check_fen(
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            &[14, 191, 2812, 43238],
+        );
+    }
+
+    #[test]
+    fn promotions_and_castling_out_of_check() {
+        check_fen(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            &[6, 264, 9467],
+        );
+        check_fen(
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            &[44, 1486, 62379],
+        );
+    }
+
+    #[test]
+    fn chess960() {
+        check_fen(
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+            &[21, 528, 12189],
+        );
+    }
+
+    #[test]
+    fn variants() {
+        for (variant, counts) in [
+            (Variant::KingOfTheHill, &[20, 400, 8902][..]),
+            (Variant::ThreeCheck, &[20, 400, 8902]),
+            (Variant::RacingKings, &[21, 421, 11264]),
+            (Variant::Antichess, &[20, 400, 8067]),
+            (Variant::Horde, &[8, 128, 1274]),
+            (Variant::Crazyhouse, &[20, 400, 8902, 197281]),
+            (Variant::Atomic, &[20, 400, 8902, 197326]),
+            (Variant::Capablanca, &[28, 784, 25228]),
+            (Variant::Gardner, &[7, 53, 506, 4775]),
+            (Variant::LosAlamos, &[10, 100, 1212, 14332]),
+        ] {
+            check(variant.start().unwrap(), counts);
+        }
+    }
+}
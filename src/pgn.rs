@@ -0,0 +1,310 @@
+use crate::movegen::{make_move, Move};
+use crate::position::Position;
+use crate::variant::Variant;
+
+//One game from a PGN file, moves still in SAN as written
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    //1-0, 0-1, 1/2-1/2 or * when unfinished or unknown
+    pub result: String,
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+//Variant tag values programs use for Chess960
+const CHESS960_NAMES: [&str; 3] = ["chess960", "chess 960", "fischerandom"];
+
+impl PgnGame {
+    fn new() -> Self {
+        PgnGame {
+            tags: vec![],
+            moves: vec![],
+            result: "*".to_string(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|x| x.0 == name).map(|x| x.1.as_str())
+    }
+
+    //Games set up from a position say so with a FEN tag, other rules with a Variant tag
+    pub fn start(&self) -> Result<Position, String> {
+        let name = self.tag("Variant").unwrap_or("Standard");
+        let chess960 = CHESS960_NAMES.contains(&name.to_lowercase().as_str());
+        let variant = if chess960 {
+            Variant::Standard
+        } else {
+            Variant::parse(name).ok_or(format!("Unknown variant {name}"))?
+        };
+        variant.load_pieces();
+
+        let mut position = match self.tag("FEN") {
+            Some(fen) => Position::from_fen(fen)?,
+            None => variant.start()?,
+        };
+        position.chess960 |= chess960;
+        if position.variant == Variant::Standard {
+            position.variant = variant;
+        }
+        Ok(position)
+    }
+
+    //Each move with the position before it, leaving out the rest from a move that can't be read
+    pub fn replay(&self, start: Position) -> impl Iterator<Item = (Position, Move)> + '_ {
+        let mut position = start;
+        self.moves.iter().map_while(move |text| {
+            let mv = Move::from_san(&position, text)?;
+            let before = position.clone();
+            make_move(&mut position, mv);
+            Some((before, mv))
+        })
+    }
+}
+
+//Every game in the files, in the order given
+pub fn read(files: &[String]) -> Result<Vec<PgnGame>, String> {
+    let mut games = vec![];
+    for file in files {
+        let text =
+            std::fs::read_to_string(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+        games.extend(parse(&text));
+    }
+    Ok(games)
+}
+
+//Reads "[Name "Value"]", with backslash escapes in the value
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((
+        name.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+//Move numbers may be stuck to the move that follows, as in 1.e4, but 0-0 is a move of its own
+fn strip_move_number(token: &str) -> &str {
+    let number = token.trim_start_matches(|x: char| x.is_ascii_digit());
+    let rest = number.trim_start_matches('.');
+    if number.len() < token.len() && rest.len() < number.len() {
+        rest
+    } else {
+        token
+    }
+}
+
+/*
+Splits a PGN file into games. Comments, variations, NAGs and move numbers are dropped as only
+the main line is needed.
+*/
+pub fn parse(text: &str) -> Vec<PgnGame> {
+    let mut games = vec![];
+    let mut game = PgnGame::new();
+    let mut in_movetext = false;
+    //Comments and variations can run over several lines
+    let mut comment = false;
+    let mut variation_depth = 0;
+
+    for line in text.lines() {
+        if line.starts_with('%') {
+            continue;
+        }
+
+        if !comment && variation_depth == 0 && line.trim_start().starts_with('[') {
+            //A tag after moves without a result means the previous game ended abruptly
+            if in_movetext {
+                games.push(std::mem::replace(&mut game, PgnGame::new()));
+                in_movetext = false;
+            }
+            game.tags.extend(parse_tag(line));
+            continue;
+        }
+
+        let mut token = String::new();
+        let mut chars = line.chars().peekable();
+        loop {
+            let c = chars.next();
+
+            if comment {
+                if c == Some('}') {
+                    comment = false;
+                }
+                if c.is_none() {
+                    break;
+                }
+                continue;
+            }
+
+            let separator = match c {
+                None => true,
+                Some(c) => c.is_whitespace() || "{}();$".contains(c),
+            };
+            if !separator {
+                token.push(c.unwrap());
+                continue;
+            }
+
+            if !token.is_empty() && variation_depth == 0 {
+                in_movetext = true;
+                let san = strip_move_number(&token);
+                if RESULTS.contains(&token.as_str()) {
+                    game.result = token.clone();
+                    games.push(std::mem::replace(&mut game, PgnGame::new()));
+                    in_movetext = false;
+                } else if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            }
+            token.clear();
+
+            match c {
+                None | Some(';') => break,
+                Some('{') => comment = true,
+                Some('(') => variation_depth += 1,
+                Some(')') => variation_depth = (variation_depth - 1).max(0),
+                //Numeric annotation glyph, the digits are skipped along with it
+                Some('$') => {
+                    while chars.peek().is_some_and(|x| x.is_ascii_digit()) {
+                        chars.next();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if in_movetext || !game.tags.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+//A move as it goes into movetext, with its annotation glyph and comment if it has them
+pub struct Annotated {
+    pub san: String,
+    pub nag: Option<u8>,
+    pub comment: Option<String>,
+}
+
+//Lines of movetext are kept under the 80 columns the standard asks for
+const LINE_LENGTH: usize = 79;
+
+//Today's date as PGN writes it, YYYY.MM.DD
+pub fn today() -> String {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() / 86400) as i64;
+
+    //Days since 1970 to a civil date, counting in 400 year eras starting in March
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+/*
+Writes one game. Tags go first in the order given, with SetUp and FEN added when the game
+doesn't start from the usual position, and Variant for anything but standard chess. Black's move
+number is repeated after a comment.
+*/
+pub fn write(
+    tags: &[(String, String)],
+    start: &Position,
+    moves: &[Annotated],
+    result: &str,
+) -> String {
+    let mut text = String::new();
+    let mut tags = tags.to_vec();
+    let fen = start.to_fen();
+    if start.variant != Variant::Standard {
+        tags.push(("Variant".to_string(), start.variant.title().to_string()));
+    } else if start.chess960 {
+        tags.push(("Variant".to_string(), "Chess960".to_string()));
+    }
+    if fen != start.variant.start_fen() || start.chess960 {
+        tags.push(("SetUp".to_string(), "1".to_string()));
+        tags.push(("FEN".to_string(), fen));
+    }
+    for (name, value) in &tags {
+        text += &format!(
+            "[{name} \"{}\"]\n",
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+    }
+    text.push('\n');
+
+    let mut tokens = vec![];
+    let mut white_to_move = start.white_to_move;
+    let mut number = start.fullmove_number;
+    let mut after_comment = true;
+    for mv in moves {
+        if white_to_move {
+            tokens.push(format!("{number}."));
+        } else if after_comment {
+            tokens.push(format!("{number}..."));
+        }
+        tokens.push(mv.san.clone());
+        if let Some(nag) = mv.nag {
+            tokens.push(format!("${nag}"));
+        }
+        after_comment = false;
+        if let Some(comment) = &mv.comment {
+            //Comments are split into words so long ones can wrap
+            tokens.push("{".to_string());
+            tokens.extend(comment.split_whitespace().map(|x| x.to_string()));
+            tokens.push("}".to_string());
+            after_comment = true;
+        }
+
+        if !white_to_move {
+            number += 1;
+        }
+        white_to_move = !white_to_move;
+    }
+    tokens.push(result.to_string());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_LENGTH {
+            text += &line;
+            text.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += &token;
+    }
+    text += &line;
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn move_numbers() {
+        let games = parse("1. e4 e5 2.Nf3 Nc6 3. Bc4 Bc5 4. 0-0 Nf6 5. d3 {A comment} 5... d6 1-0");
+        assert_eq!(games.len(), 1);
+        assert_eq!(
+            games[0].moves,
+            ["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5", "0-0", "Nf6", "d3", "d6"]
+        );
+        assert_eq!(games[0].result, "1-0");
+    }
+}
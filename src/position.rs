@@ -0,0 +1,535 @@
+use crate::bitboard::{bit, square_index, Bitboards, FILES};
+use crate::fairy;
+use crate::variant::{pocket_index, Variant, POCKET};
+use crate::zobrist;
+use crate::{default_pieces, Piece, PieceType};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+//Indexes into Position::castling
+pub const WHITE_KING_SIDE: usize = 0;
+pub const WHITE_QUEEN_SIDE: usize = 1;
+pub const BLACK_KING_SIDE: usize = 2;
+pub const BLACK_QUEEN_SIDE: usize = 3;
+
+//The widest and tallest boards that fit in the bitboards
+pub const MAX_WIDTH: u8 = FILES as u8;
+pub const MAX_HEIGHT: u8 = 8;
+
+//Rook columns for castling in the usual setup, in the order of the rights
+fn classical_rooks(width: u8) -> [u8; 4] {
+    [width - 1, 0, width - 1, 0]
+}
+
+//Knight placements on the five squares left once bishops and queen are placed, by Scharnagl's count
+const KNIGHT_SQUARES: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+//Everything needed to continue a game besides its history
+#[derive(Clone)]
+pub struct Position {
+    pub pieces: Vec<Piece>,
+    //Files and ranks, rows of smaller boards starting further down so the bottom one is still 7
+    pub width: u8,
+    pub height: u8,
+    pub white_to_move: bool,
+    pub castling: [bool; 4],
+    //Column of the rook each castling right castles with
+    pub castling_rooks: [u8; 4],
+    //Chess960 castling, written as the king taking its own rook
+    pub chess960: bool,
+    pub variant: Variant,
+    //Checks given by white and black, counted in three-check
+    pub checks: [u8; 2],
+    //Pieces white and black have taken in crazyhouse, counted by the order of variant::POCKET
+    pub pockets: [[u8; 5]; 2],
+    //Squares of pieces that were pawns once, which go back to being pawns when taken
+    pub promoted: u128,
+    //Square a pawn passed over with its double step, where it can be struck en passant
+    pub en_passant: Option<(u8, u8)>,
+    //Moves since the last capture or pawn move, for the fifty move rule
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    //Zobrist key of everything above but the move counters
+    pub hash: u64,
+    //The same pieces again as bitboards, for move generation
+    pub board: Bitboards,
+}
+
+pub fn castling_index(team_white: bool, king_side: bool) -> usize {
+    match (team_white, king_side) {
+        (true, true) => WHITE_KING_SIDE,
+        (true, false) => WHITE_QUEEN_SIDE,
+        (false, true) => BLACK_KING_SIDE,
+        (false, false) => BLACK_QUEEN_SIDE,
+    }
+}
+
+pub fn king_side(right: usize) -> bool {
+    right == WHITE_KING_SIDE || right == BLACK_KING_SIDE
+}
+
+//Row of the team's pieces at the start of the game, on a board of the given height
+pub fn home_row(team_white: bool, height: u8) -> u8 {
+    if team_white {
+        7
+    } else {
+        MAX_HEIGHT - height
+    }
+}
+
+/*
+Standard square names run from a1 at white's side of the board. Rows in piece positions start on
+black's side, so the rank is mirrored.
+*/
+pub fn square_name(square: (u8, u8)) -> String {
+    format!("{}{}", (b'a' + square.0) as char, 8 - square.1)
+}
+
+pub fn parse_square(name: &str) -> Option<(u8, u8)> {
+    let chars = name.as_bytes();
+    if chars.len() != 2 || !(b'a'..=b'p').contains(&chars[0]) || !(b'1'..=b'8').contains(&chars[1])
+    {
+        return None;
+    }
+
+    Some((chars[0] - b'a', 8 - (chars[1] - b'0')))
+}
+
+pub fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+        PieceType::Fairy(i) => fairy::get(i).letter,
+    }
+}
+
+pub fn piece_from_letter(letter: char) -> Option<PieceType> {
+    match letter.to_ascii_lowercase() {
+        'p' => Some(PieceType::Pawn),
+        'n' => Some(PieceType::Knight),
+        'b' => Some(PieceType::Bishop),
+        'r' => Some(PieceType::Rook),
+        'q' => Some(PieceType::Queen),
+        'k' => Some(PieceType::King),
+        v => fairy::pieces()
+            .iter()
+            .position(|x| x.letter == v)
+            .map(|i| PieceType::Fairy(i as u8)),
+    }
+}
+
+impl Position {
+    pub fn startpos() -> Self {
+        let pieces = default_pieces();
+        let mut position = Position {
+            board: Bitboards::from_pieces(&pieces),
+            pieces,
+            width: 8,
+            height: 8,
+            white_to_move: true,
+            castling: [true; 4],
+            castling_rooks: classical_rooks(8),
+            chess960: false,
+            variant: Variant::Standard,
+            checks: [0; 2],
+            pockets: [[0; 5]; 2],
+            promoted: 0,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            hash: 0,
+        };
+        position.hash = zobrist::hash(&position);
+        position
+    }
+
+    /*
+    One of the 960 Fischer random setups by its standard number, 518 being the usual one.
+    Bishops go on opposite colours, then the queen and knights on the squares left, and the king
+    between the two rooks on the last three.
+    */
+    pub fn chess960(index: u16) -> Self {
+        let mut rank = [None; 8];
+        let mut n = index as usize % 960;
+
+        rank[n % 4 * 2 + 1] = Some('b');
+        n /= 4;
+        rank[n % 4 * 2] = Some('b');
+        n /= 4;
+
+        let empty = |rank: &[Option<char>; 8]| {
+            (0..8)
+                .filter(|&x| rank[x].is_none())
+                .collect::<Vec<usize>>()
+        };
+        rank[empty(&rank)[n % 6]] = Some('q');
+        n /= 6;
+
+        let left = empty(&rank);
+        let (first, second) = KNIGHT_SQUARES[n];
+        rank[left[first]] = Some('n');
+        rank[left[second]] = Some('n');
+
+        for (square, piece) in empty(&rank).into_iter().zip(['r', 'k', 'r']) {
+            rank[square] = Some(piece);
+        }
+
+        let black = rank.iter().map(|x| x.unwrap()).collect::<String>();
+        let mut position = Position::from_fen(&format!(
+            "{black}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1",
+            black.to_uppercase()
+        ))
+        .unwrap();
+        position.chess960 = true;
+        position
+    }
+
+    //Read off the bitboards rather than searching the piece list
+    pub fn piece_at(&self, square: (u8, u8)) -> Option<Piece> {
+        let (team_white, piece_type) = self.board.piece_at(square_index(square))?;
+        Some(Piece {
+            team_white,
+            piece_type,
+            position: square,
+        })
+    }
+
+    //Top row of the board, 0 unless it has fewer than eight ranks
+    pub fn top(&self) -> u8 {
+        MAX_HEIGHT - self.height
+    }
+
+    //Squares on the board, as the bitboards have room for more
+    pub fn area(&self) -> u128 {
+        let rank = (1u128 << self.width) - 1;
+        (0..self.height as usize).fold(0, |area, x| area | rank << (x * FILES))
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, String> {
+        let mut fields = fen.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 4 {
+            return Err(format!("Incomplete FEN {fen}"));
+        }
+
+        /*
+        Three-check counts come before the move counters as the checks each side has left, like
+        3+3, or after them as the checks each has given, like +1+0.
+        */
+        let mut checks = None;
+        if let Some(i) = (4..fields.len()).find(|&i| fields[i].contains('+')) {
+            let text = fields.remove(i);
+            let counts = text
+                .trim_start_matches('+')
+                .split('+')
+                .map(|x| x.parse::<u8>().ok().filter(|&x| x <= 3))
+                .collect::<Option<Vec<u8>>>()
+                .filter(|x| x.len() == 2)
+                .ok_or(format!("Invalid check counts {text}"))?;
+            checks = Some(if text.starts_with('+') {
+                [counts[0], counts[1]]
+            } else {
+                [3 - counts[0], 3 - counts[1]]
+            });
+        }
+
+        //Crazyhouse pockets follow the board in brackets, or sometimes as a ninth row
+        let (board, pocket) = match fields[0].split_once('[') {
+            Some((board, pocket)) => (board, Some(pocket.trim_end_matches(']'))),
+            None if fields[0].matches('/').count() == 8 => fields[0]
+                .rsplit_once('/')
+                .map_or((fields[0], None), |(board, pocket)| (board, Some(pocket))),
+            None => (fields[0], None),
+        };
+
+        let rows = board.split('/').collect::<Vec<&str>>();
+        if rows.len() > MAX_HEIGHT as usize {
+            return Err(format!(
+                "FEN board has {} rows, at most {MAX_HEIGHT} fit",
+                rows.len()
+            ));
+        }
+        let height = rows.len() as u8;
+        let top = MAX_HEIGHT - height;
+
+        //The first row says how wide the board is, the others have to match it
+        let mut width = None;
+        let mut pieces: Vec<Piece> = vec![];
+        let mut promoted = 0;
+        //FEN starts from black's side of the board, same as piece positions
+        for (y, row) in rows.iter().enumerate() {
+            let y = top + y as u8;
+            let mut x = 0u8;
+            //Empty squares can run into two digits on wide boards
+            let mut empty = 0;
+            for c in row.chars() {
+                if let Some(digit) = c.to_digit(10) {
+                    empty = empty * 10 + digit;
+                    continue;
+                }
+                x = x.saturating_add(empty.min(u8::MAX as u32) as u8);
+                empty = 0;
+
+                //A tilde marks the piece before it as a promoted pawn
+                if c == '~' {
+                    let piece = pieces.last().ok_or("Tilde without a piece in FEN")?;
+                    promoted |= bit(piece.position);
+                    continue;
+                }
+
+                let piece_type = piece_from_letter(c).ok_or(format!("Unknown piece {c} in FEN"))?;
+                if x >= MAX_WIDTH {
+                    return Err(format!("FEN row {} is too long", y - top + 1));
+                }
+                pieces.push(Piece {
+                    team_white: c.is_ascii_uppercase(),
+                    piece_type,
+                    position: (x, y),
+                });
+                x += 1;
+            }
+            x = x.saturating_add(empty.min(u8::MAX as u32) as u8);
+
+            match width {
+                _ if x == 0 || x > MAX_WIDTH => {
+                    return Err(format!(
+                        "FEN row {} has {x} squares, a board can be 1 to {MAX_WIDTH} wide",
+                        y - top + 1
+                    ))
+                }
+                Some(v) if v != x => {
+                    return Err(format!(
+                        "FEN row {} has {x} squares where the first has {v}",
+                        y - top + 1
+                    ))
+                }
+                _ => width = Some(x),
+            }
+        }
+        let width = width.unwrap_or(8);
+
+        let white_to_move = match fields[1] {
+            "w" => true,
+            "b" => false,
+            v => return Err(format!("Unknown side to move {v}")),
+        };
+
+        /*
+        KQkq castle with the outermost rook on that side of the king, as in X-FEN. Shredder-FEN
+        names the rook's file instead, which X-FEN also uses when an inner rook is the one.
+        */
+        let mut castling = [false; 4];
+        let mut castling_rooks = classical_rooks(width);
+        for c in fields[2].chars() {
+            if c == '-' {
+                continue;
+            }
+            let team_white = c.is_ascii_uppercase();
+            let row = home_row(team_white, height);
+            let king = pieces
+                .iter()
+                .find(|x| {
+                    x.team_white == team_white
+                        && x.piece_type == PieceType::King
+                        && x.position.1 == row
+                })
+                .ok_or(format!("Castling right {c} without a king on its home row"))?
+                .position
+                .0;
+            let rooks = pieces
+                .iter()
+                .filter(|x| {
+                    x.team_white == team_white
+                        && x.piece_type == PieceType::Rook
+                        && x.position.1 == row
+                })
+                .map(|x| x.position.0)
+                .collect::<Vec<u8>>();
+
+            let rook = match c.to_ascii_lowercase() {
+                'k' => rooks.iter().copied().filter(|&x| x > king).max(),
+                'q' => rooks.iter().copied().filter(|&x| x < king).min(),
+                file @ 'a'..='p' => Some(file as u8 - b'a').filter(|x| rooks.contains(x)),
+                _ => return Err(format!("Unknown castling right {c}")),
+            }
+            .ok_or(format!("Castling right {c} without a rook to castle with"))?;
+
+            let right = castling_index(team_white, rook > king);
+            castling[right] = true;
+            castling_rooks[right] = rook;
+        }
+
+        //Castling from anywhere but the usual squares, the king in the middle, can only be Chess960
+        let chess960 = (0..4).any(|i| {
+            castling[i]
+                && (castling_rooks[i] != classical_rooks(width)[i]
+                    || pieces.iter().any(|x| {
+                        x.piece_type == PieceType::King
+                            && x.team_white == (i < BLACK_KING_SIDE)
+                            && x.position.0 != width / 2
+                    }))
+        });
+
+        let mut pockets = [[0; 5]; 2];
+        for c in pocket.unwrap_or("").chars().filter(|&x| x != '-') {
+            let index = piece_from_letter(c)
+                .and_then(pocket_index)
+                .ok_or(format!("Piece {c} can't be in a pocket"))?;
+            pockets[c.is_ascii_lowercase() as usize][index] += 1;
+        }
+
+        let en_passant = match fields[3] {
+            "-" => None,
+            v => Some(parse_square(v).ok_or(format!("Invalid en passant square {v}"))?),
+        };
+
+        let number = |index: usize, default: u32| {
+            fields
+                .get(index)
+                .map_or(Ok(default), |x| x.parse::<u32>())
+                .map_err(|_| format!("Invalid move counter {}", fields[index]))
+        };
+
+        let mut position = Position {
+            board: Bitboards::from_pieces(&pieces),
+            pieces,
+            width,
+            height,
+            white_to_move,
+            castling,
+            castling_rooks,
+            chess960,
+            //Only crazyhouse has pockets and only three-check counts checks
+            variant: if pocket.is_some() {
+                Variant::Crazyhouse
+            } else if checks.is_some() {
+                Variant::ThreeCheck
+            } else {
+                Variant::Standard
+            },
+            checks: checks.unwrap_or([0; 2]),
+            pockets,
+            promoted,
+            en_passant,
+            halfmove_clock: number(4, 0)?,
+            fullmove_number: number(5, 1)?,
+            hash: 0,
+        };
+        position.hash = zobrist::hash(&position);
+        Ok(position)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut rows = vec![];
+        for y in self.top()..MAX_HEIGHT {
+            let mut row = String::new();
+            let mut empty = 0;
+            for x in 0..self.width {
+                match self.piece_at((x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row += &empty.to_string();
+                            empty = 0;
+                        }
+                        let letter = piece_letter(piece.piece_type);
+                        row.push(if piece.team_white {
+                            letter.to_ascii_uppercase()
+                        } else {
+                            letter
+                        });
+                        if self.promoted & bit((x, y)) != 0 {
+                            row.push('~');
+                        }
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row += &empty.to_string();
+            }
+            rows.push(row);
+        }
+
+        //Chess960 rooks that aren't the outermost ones are named by their file
+        let castling = "KQkq"
+            .chars()
+            .enumerate()
+            .filter(|(i, _)| self.castling[*i])
+            .map(|(i, c)| {
+                let row = home_row(i < BLACK_KING_SIDE, self.height);
+                let rook = self.castling_rooks[i];
+                let outermost = !self.pieces.iter().any(|x| {
+                    x.piece_type == PieceType::Rook
+                        && x.team_white == c.is_ascii_uppercase()
+                        && x.position.1 == row
+                        && if king_side(i) {
+                            x.position.0 > rook
+                        } else {
+                            x.position.0 < rook
+                        }
+                });
+
+                if !self.chess960 || outermost {
+                    c
+                } else if c.is_ascii_uppercase() {
+                    (b'A' + rook) as char
+                } else {
+                    (b'a' + rook) as char
+                }
+            })
+            .collect::<String>();
+
+        //Queens first, the way pockets are usually written
+        let mut board = rows.join("/");
+        if self.variant == Variant::Crazyhouse {
+            board.push('[');
+            for (team, pocket) in self.pockets.iter().enumerate() {
+                for (i, &count) in pocket.iter().enumerate().rev() {
+                    let letter = piece_letter(POCKET[i]);
+                    let letter = if team == 0 {
+                        letter.to_ascii_uppercase()
+                    } else {
+                        letter
+                    };
+                    board.extend(std::iter::repeat_n(letter, count as usize));
+                }
+            }
+            board.push(']');
+        }
+
+        let checks = if self.variant == Variant::ThreeCheck {
+            format!(
+                " {}+{}",
+                3u8.saturating_sub(self.checks[0]),
+                3u8.saturating_sub(self.checks[1])
+            )
+        } else {
+            String::new()
+        };
+
+        format!(
+            "{} {} {} {}{} {} {}",
+            board,
+            if self.white_to_move { "w" } else { "b" },
+            if castling.is_empty() { "-" } else { &castling },
+            self.en_passant.map_or("-".to_string(), square_name),
+            checks,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+}
@@ -0,0 +1,704 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::engine::{self, Difficulty, Limits, Rng};
+use crate::hint::checkmated;
+use crate::movegen::{legal_moves, make_move, Move};
+use crate::pgn;
+use crate::position::Position;
+use crate::variant::{is_capture, Variant};
+use crate::{
+    describe_move, format_location, highlight_squares, read_input, render_board, take_move_input,
+};
+
+pub const USAGE: &str = "Usage: chess puzzles <file.csv> [--record <file>]";
+pub const MINE_USAGE: &str =
+    "Usage: chess mine-puzzles <pgn>... --output <file> [--seconds N] [--gap N]";
+
+//First line of a written puzzle file, which reading skips like any line that isn't a puzzle
+const HEADER: &str = "PuzzleId,FEN,Moves,Rating";
+
+//Where solved and failed puzzles are kept when no --record is given
+const DEFAULT_RECORD: &str = "puzzle-record.csv";
+//Rating of a first timer, and of puzzles that don't give one
+const START_RATING: i32 = 1500;
+//Most a rating moves for one puzzle
+const RATING_STEP: f64 = 32.0;
+
+/*
+One line of a puzzle file in the usual layout: id, FEN, moves in UCI notation and rating, with any
+later columns ignored. The first move is the opponent's, the one that sets the puzzle up.
+*/
+pub struct Puzzle {
+    pub id: String,
+    pub fen: String,
+    pub moves: Vec<String>,
+    pub rating: i32,
+}
+
+impl Puzzle {
+    fn parse(line: &str) -> Option<Puzzle> {
+        let fields = line.split(',').collect::<Vec<&str>>();
+        let moves = fields
+            .get(2)?
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+        if fields[0].is_empty() || moves.len() < 2 {
+            return None;
+        }
+
+        Some(Puzzle {
+            id: fields[0].to_string(),
+            fen: fields[1].to_string(),
+            moves,
+            rating: fields
+                .get(3)
+                .and_then(|x| x.parse::<i32>().ok())
+                .unwrap_or(START_RATING),
+        })
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.id,
+            self.fen,
+            self.moves.join(" "),
+            self.rating
+        )
+    }
+}
+
+//Puzzles in the file, skipping the header and any line that isn't one
+pub fn read(path: &str) -> Result<Vec<Puzzle>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    Ok(text.lines().filter_map(Puzzle::parse).collect())
+}
+
+//The player's standing, worked out again from the record file every time
+struct Record {
+    rating: i32,
+    streak: u32,
+    best_streak: u32,
+    played: HashSet<String>,
+}
+
+impl Record {
+    //Each line is a puzzle id, solved or failed, and the rating after it
+    fn read(path: &str) -> Result<Record, String> {
+        let mut record = Record {
+            rating: START_RATING,
+            streak: 0,
+            best_streak: 0,
+            played: HashSet::new(),
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(record),
+            Err(e) => return Err(format!("Could not read {path}: {e}")),
+        };
+
+        for line in text.lines() {
+            let fields = line.split(',').collect::<Vec<&str>>();
+            let (Some(id), Some(result), Some(rating)) =
+                (fields.first(), fields.get(1), fields.get(2))
+            else {
+                continue;
+            };
+            record.played.insert(id.to_string());
+            record.add(*result == "solved", rating.parse().unwrap_or(record.rating));
+        }
+        Ok(record)
+    }
+
+    fn add(&mut self, solved: bool, rating: i32) {
+        self.rating = rating;
+        self.streak = if solved { self.streak + 1 } else { 0 };
+        self.best_streak = self.best_streak.max(self.streak);
+    }
+
+    //Elo's update, as if the puzzle were an opponent of its rating
+    fn rated(&self, solved: bool, puzzle_rating: i32) -> i32 {
+        let expected = 1.0 / (1.0 + 10f64.powf((puzzle_rating - self.rating) as f64 / 400.0));
+        let score = if solved { 1.0 } else { 0.0 };
+        self.rating + (RATING_STEP * (score - expected)).round() as i32
+    }
+}
+
+fn append(path: &str, line: &str) -> Result<(), String> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut x| writeln!(x, "{line}"))
+        .map_err(|e| format!("Could not write {path}: {e}"))
+}
+
+//The start position and the puzzle's moves, checked to be legal one after another
+fn moves(puzzle: &Puzzle) -> Result<(Position, Vec<Move>), String> {
+    let start = Position::from_fen(&puzzle.fen)?;
+    let mut position = start.clone();
+    let mut line = vec![];
+    for text in &puzzle.moves {
+        let mv = Move::from_uci(&position, text)
+            .ok_or(format!("Illegal move {text} in puzzle {}", puzzle.id))?;
+        line.push(mv);
+        make_move(&mut position, mv);
+    }
+    Ok((start, line))
+}
+
+//The solution's move is right, and so is a mate other than the one given
+fn accepts(position: &Position, mv: Move, expected: Move) -> bool {
+    let mut after = position.clone();
+    make_move(&mut after, mv);
+    mv == expected || checkmated(&after)
+}
+
+/*
+Plays the opponent's first move and then takes the player's moves, answering each right one with
+the next move of the solution. Taking a hint means the puzzle counts as failed, even when it's
+finished.
+*/
+fn solve(puzzle: &Puzzle) -> Result<bool, String> {
+    let (mut position, line) = moves(puzzle)?;
+    let top = position.top();
+    make_move(&mut position, line[0]);
+    let mut last = line[0];
+    println!("Opponent played {}", describe_move(last, top));
+    println!(
+        "{} to move, find the best move",
+        if position.white_to_move {
+            "White"
+        } else {
+            "Black"
+        }
+    );
+
+    let mut hinted = false;
+    let mut index = 1;
+    while index < line.len() {
+        let expected = line[index];
+        println!(
+            "{}",
+            highlight_squares(&render_board(&position), &[last.from, last.to], top)
+        );
+
+        let legal = legal_moves(&position);
+        let mv = match take_move_input(&position, &legal, position.white_to_move, None) {
+            Ok(v) => v,
+            Err(command) if command[0] == "HINT" => {
+                hinted = true;
+                println!(
+                    "Hint: move the piece on {}",
+                    format_location(expected.from, top)
+                );
+                continue;
+            }
+            Err(_) => {
+                println!("Only HINT can be used while solving a puzzle");
+                continue;
+            }
+        };
+        let right = accepts(&position, mv, expected);
+        make_move(&mut position, mv);
+
+        if !right {
+            println!(
+                "Not the move, the solution was {}",
+                describe_move(expected, top)
+            );
+            return Ok(false);
+        }
+        if checkmated(&position) {
+            break;
+        }
+
+        if let Some(&reply) = line.get(index + 1) {
+            make_move(&mut position, reply);
+            println!("Opponent plays {}", describe_move(reply, top));
+            last = reply;
+        }
+        index += 2;
+    }
+
+    if hinted {
+        println!("Solved with a hint, which counts as failed");
+    }
+    Ok(!hinted)
+}
+
+fn parse_args(args: &[String]) -> Result<(String, String), String> {
+    let mut file = None;
+    let mut record = DEFAULT_RECORD.to_string();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = args.next().ok_or("--record expects a file")?.clone(),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument {arg}")),
+            _ if file.is_none() => file = Some(arg.clone()),
+            _ => return Err("puzzles takes a single puzzle file".to_string()),
+        }
+    }
+
+    Ok((file.ok_or("puzzles needs a puzzle file")?, record))
+}
+
+/*
+chess puzzles, returns whether it ran. Puzzles come one after another, each the unplayed one rated
+closest to the player, with every result written to the record as soon as it's known.
+*/
+pub fn run(args: &[String]) -> bool {
+    let result = parse_args(args)
+        .map_err(|e| format!("{e}\n{USAGE}"))
+        .and_then(|(file, path)| {
+            let puzzles = read(&file)?;
+            if puzzles.is_empty() {
+                return Err(format!("No puzzles found in {file}"));
+            }
+            let mut record = Record::read(&path)?;
+            println!(
+                "{} puzzles, {} played before. Type HINT for the piece to move",
+                puzzles.len(),
+                puzzles
+                    .iter()
+                    .filter(|x| record.played.contains(&x.id))
+                    .count()
+            );
+
+            loop {
+                let Some(puzzle) = puzzles
+                    .iter()
+                    .filter(|x| !record.played.contains(&x.id))
+                    .min_by_key(|x| (x.rating - record.rating).abs())
+                else {
+                    println!("Every puzzle in {file} has been played");
+                    return Ok(());
+                };
+                record.played.insert(puzzle.id.clone());
+
+                println!(
+                    "\nPuzzle {} rated {}, your rating {}",
+                    puzzle.id, puzzle.rating, record.rating
+                );
+                let solved = match solve(puzzle) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("{e}, skipping it");
+                        continue;
+                    }
+                };
+
+                let rating = record.rated(solved, puzzle.rating);
+                let change = rating - record.rating;
+                record.add(solved, rating);
+                append(
+                    &path,
+                    &format!(
+                        "{},{},{rating}",
+                        puzzle.id,
+                        if solved { "solved" } else { "failed" }
+                    ),
+                )?;
+
+                println!(
+                    "{} Rating {rating} ({change:+}), streak {} (best {})",
+                    if solved { "Solved!" } else { "Failed." },
+                    record.streak,
+                    record.best_streak
+                );
+                println!("Press enter for the next puzzle or type QUIT to stop");
+                if read_input().trim().eq_ignore_ascii_case("quit") {
+                    return Ok(());
+                }
+            }
+        });
+
+    if let Err(e) = &result {
+        println!("{e}");
+    }
+    result.is_ok()
+}
+
+//Search time for each position when mining and no --seconds is given
+const MINE_TIME: Duration = Duration::from_millis(500);
+//Centipawns the best move must be ahead of every other by, when no --gap is given
+const MINE_GAP: i32 = 300;
+//Below this the side to move isn't winning, so a puzzle's other moves must all score less
+const WINNING: i32 = 200;
+//Longest solution, in the solver's moves
+const MAX_SOLUTION: usize = 4;
+
+struct MineOptions {
+    files: Vec<String>,
+    output: String,
+    time: Duration,
+    gap: i32,
+}
+
+fn parse_mine_args(args: &[String]) -> Result<MineOptions, String> {
+    let mut options = MineOptions {
+        files: vec![],
+        output: String::new(),
+        time: MINE_TIME,
+        gap: MINE_GAP,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} expects a value"));
+        match arg.as_str() {
+            "--output" => options.output = value(arg)?.clone(),
+            "--seconds" => {
+                options.time = value(arg)?
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| *x > 0.0)
+                    .map(Duration::from_secs_f64)
+                    .ok_or("--seconds expects a positive number")?
+            }
+            "--gap" => {
+                options.gap = value(arg)?
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or("--gap expects centipawns")?
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument {arg}")),
+            _ => options.files.push(arg.clone()),
+        }
+    }
+
+    if options.files.is_empty() || options.output.is_empty() {
+        return Err("mine-puzzles needs PGN files and an --output file".to_string());
+    }
+    Ok(options)
+}
+
+//Score for the side to move and the line to it, None when the search found nothing
+fn analyse(
+    position: &Position,
+    time: Duration,
+    moves: Option<Vec<Move>>,
+) -> Option<(i32, Vec<Move>)> {
+    let mut found = None;
+    engine::search(
+        position,
+        &Difficulty::from_level(engine::MAX_LEVEL),
+        &Limits {
+            time: Some(time),
+            moves,
+            ..Default::default()
+        },
+        &AtomicBool::new(false),
+        &mut Rng::new(),
+        &mut |info| found = Some((info.score, info.pv.clone())),
+    );
+    found.filter(|x| !x.1.is_empty())
+}
+
+/*
+The best move's score, the score of the best of the rest and the best line. Searching again
+without the best move is what tells how far ahead of the others it is.
+*/
+fn best_and_second(position: &Position, time: Duration) -> Option<(i32, i32, Vec<Move>)> {
+    let (best, line) = analyse(position, time, None)?;
+    let others = legal_moves(position)
+        .into_iter()
+        .filter(|&x| x != line[0])
+        .collect::<Vec<Move>>();
+    if others.is_empty() {
+        return None;
+    }
+    let (second, _) = analyse(position, time, Some(others))?;
+    Some((best, second, line))
+}
+
+/*
+Follows the best line for as long as each of the solver's moves stays the only good one, ending on
+one of them. The replies are the engine's.
+*/
+fn forcing_line(position: &Position, line: Vec<Move>, options: &MineOptions) -> Vec<Move> {
+    let mut position = position.clone();
+    let mut solution = vec![line[0]];
+    let mut line = line;
+
+    while solution.len() < MAX_SOLUTION * 2 - 1 {
+        make_move(&mut position, line[0]);
+        let Some(&reply) = line.get(1) else {
+            break;
+        };
+        make_move(&mut position, reply);
+
+        match best_and_second(&position, options.time) {
+            Some((best, second, next)) if best - second >= options.gap => {
+                solution.extend([reply, next[0]]);
+                line = next;
+            }
+            _ => break,
+        }
+    }
+    solution
+}
+
+//A rough guess, longer solutions and quiet first moves being harder to find
+fn estimate_rating(position: &Position, solution: &[Move]) -> i32 {
+    let quiet = !is_capture(position, solution[0]) && solution[0].promotion.is_none();
+    1000 + 300 * (solution.len() as i32 / 2) + if quiet { 300 } else { 0 }
+}
+
+/*
+A puzzle if the side to move has one winning move and nothing else comes close, set up from the
+position before the opponent's move so the trainer can play that first.
+*/
+fn find_puzzle(
+    before: &Position,
+    opponent: Move,
+    position: &Position,
+    options: &MineOptions,
+) -> Option<Puzzle> {
+    let (best, second, line) = best_and_second(position, options.time)?;
+    if best < WINNING || second >= WINNING || best - second < options.gap {
+        return None;
+    }
+
+    let solution = forcing_line(position, line, options);
+    Some(Puzzle {
+        id: format!("{:016x}", position.hash),
+        fen: before.to_fen(),
+        moves: [opponent]
+            .iter()
+            .chain(&solution)
+            .map(|x| x.to_uci())
+            .collect(),
+        rating: estimate_rating(position, &solution),
+    })
+}
+
+/*
+Searches every position of every game, writing each puzzle as soon as it's found so a long run
+can be stopped early. Positions the solver faces in a puzzle already written aren't searched again,
+whether they come up later in the same game or in another one.
+*/
+fn mine(options: &MineOptions) -> Result<usize, String> {
+    std::fs::write(&options.output, format!("{HEADER}\n"))
+        .map_err(|e| format!("Could not write {}: {e}", options.output))?;
+    let mut covered = HashSet::new();
+    let mut written = 0;
+    let mut skipped = 0;
+
+    let games = pgn::read(&options.files)?;
+    for (i, game) in games.iter().enumerate() {
+        //The trainer sets puzzles up from FEN alone, which has no say in the rules
+        let Some(start) = game.start().ok().filter(|x| x.variant == Variant::Standard) else {
+            skipped += 1;
+            continue;
+        };
+
+        let mut count = 0;
+        for (before, opponent) in game.replay(start) {
+            let mut position = before.clone();
+            make_move(&mut position, opponent);
+            if covered.contains(&position.hash) {
+                continue;
+            }
+            let Some(puzzle) = find_puzzle(&before, opponent, &position, options) else {
+                continue;
+            };
+            append(&options.output, &puzzle.to_csv())?;
+            count += 1;
+
+            let mut position = before;
+            for (j, text) in puzzle.moves.iter().enumerate() {
+                let Some(mv) = Move::from_uci(&position, text) else {
+                    break;
+                };
+                make_move(&mut position, mv);
+                if j % 2 == 0 {
+                    covered.insert(position.hash);
+                }
+            }
+        }
+        written += count;
+        println!("Game {} of {}, {count} puzzles", i + 1, games.len());
+    }
+
+    if skipped > 0 {
+        println!("Skipped {skipped} games with an invalid start position or in a variant");
+    }
+    Ok(written)
+}
+
+//chess mine-puzzles, returns whether it succeeded
+pub fn run_miner(args: &[String]) -> bool {
+    let result = parse_mine_args(args)
+        .map_err(|e| format!("{e}\n{MINE_USAGE}"))
+        .and_then(|options| {
+            let count = mine(&options)?;
+            println!("Wrote {count} puzzles to {}", options.output);
+            Ok(())
+        });
+
+    if let Err(e) = &result {
+        println!("{e}");
+    }
+    result.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Black's pawn move lets either rook mate along the back row
+    const BACK_ROW: &str = "6k1/1p3ppp/8/8/8/8/8/R3R1K1 b - - 0 1";
+
+    fn puzzle(moves: &str) -> Puzzle {
+        Puzzle::parse(&format!("test,{BACK_ROW},{moves},1200")).unwrap()
+    }
+
+    fn temp_path(test: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("chess-puzzle-{test}-{}.csv", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn parse() {
+        //Later columns, as in the Lichess database, are ignored
+        let line = "00sHx,q3k1nr/1pp1nQpp/3p4/1P2p3/4P3/B1PP1b2/B5PP/5K2 b k - 0 17,\
+                    e8d7 a2e6 d7d8 f7f8,1760,80,83,72,mate mateIn2";
+        let puzzle = Puzzle::parse(line).unwrap();
+        assert_eq!(puzzle.id, "00sHx");
+        assert_eq!(puzzle.moves, ["e8d7", "a2e6", "d7d8", "f7f8"]);
+        assert_eq!(puzzle.rating, 1760);
+        assert_eq!(puzzle.to_csv().split(',').count(), 4);
+
+        assert_eq!(
+            Puzzle::parse("id,fen,e2e4 e7e5,").unwrap().rating,
+            START_RATING
+        );
+        assert!(Puzzle::parse(HEADER).is_none());
+        assert!(Puzzle::parse("id,fen,e2e4,1500").is_none());
+        assert!(Puzzle::parse("").is_none());
+    }
+
+    #[test]
+    fn legal_moves_only() {
+        let (start, line) = moves(&puzzle("b7b6 a1a8")).unwrap();
+        assert!(!start.white_to_move);
+        assert_eq!(line.len(), 2);
+
+        assert_eq!(
+            moves(&puzzle("b7b6 a1a9")).err().unwrap(),
+            "Illegal move a1a9 in puzzle test"
+        );
+        assert!(moves(&puzzle("b7b5 b7b6")).is_err());
+    }
+
+    #[test]
+    fn any_mate_solves_it() {
+        let (mut position, line) = moves(&puzzle("b7b6 a1a8")).unwrap();
+        make_move(&mut position, line[0]);
+
+        assert!(accepts(&position, line[1], line[1]));
+        let other_mate = Move::from_uci(&position, "e1e8").unwrap();
+        assert!(accepts(&position, other_mate, line[1]));
+        let check = Move::from_uci(&position, "a1a7").unwrap();
+        assert!(!accepts(&position, check, line[1]));
+    }
+
+    #[test]
+    fn elo() {
+        let record = Record::read(&temp_path("missing")).unwrap();
+        assert_eq!(record.rating, START_RATING);
+        //Evenly matched, so half the step either way
+        assert_eq!(record.rated(true, START_RATING), START_RATING + 16);
+        assert_eq!(record.rated(false, START_RATING), START_RATING - 16);
+        //Failing a much harder puzzle costs little, solving it gains nearly the whole step
+        assert_eq!(record.rated(false, START_RATING + 400), START_RATING - 3);
+        assert_eq!(record.rated(true, START_RATING + 400), START_RATING + 29);
+    }
+
+    #[test]
+    fn record() {
+        let path = temp_path("record");
+        std::fs::write(&path, "").unwrap();
+        for line in [
+            "a,solved,1516",
+            "b,solved,1530",
+            "not a result",
+            "c,failed,1514",
+        ] {
+            append(&path, line).unwrap();
+        }
+        append(&path, "d,solved,1529").unwrap();
+        let record = Record::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(record.rating, 1529);
+        assert_eq!(record.streak, 1);
+        assert_eq!(record.best_streak, 2);
+        assert_eq!(record.played.len(), 4);
+        assert!(record.played.contains("c"));
+    }
+
+    fn mine_options() -> MineOptions {
+        MineOptions {
+            files: vec![],
+            output: String::new(),
+            time: MINE_TIME,
+            gap: MINE_GAP,
+        }
+    }
+
+    fn uci(moves: &[Move]) -> Vec<String> {
+        moves.iter().map(|x| x.to_uci()).collect()
+    }
+
+    #[test]
+    fn only_move() {
+        //Mate, while anything else leaves white a queen against a rook
+        let position = Position::from_fen("6k1/5ppp/8/8/8/8/q7/4R1K1 w - - 0 1").unwrap();
+        let (best, second, line) = best_and_second(&position, MINE_TIME).unwrap();
+        assert!(best > WINNING && second < 0);
+        assert_eq!(uci(&line), ["e1e8"]);
+        assert_eq!(
+            uci(&forcing_line(&position, line, &mine_options())),
+            ["e1e8"]
+        );
+    }
+
+    #[test]
+    fn legals_mate() {
+        let before = Position::from_fen(
+            "rn1qkbnr/ppp2p1p/3p2p1/4N3/2B1P1b1/2N5/PPPP1PPP/R1BQK2R b KQkq - 0 5",
+        )
+        .unwrap();
+        let opponent = Move::from_uci(&before, "g4d1").unwrap();
+        let mut position = before.clone();
+        make_move(&mut position, opponent);
+
+        let puzzle = find_puzzle(&before, opponent, &position, &mine_options()).unwrap();
+        assert_eq!(puzzle.fen, before.to_fen());
+        assert_eq!(puzzle.moves, ["g4d1", "c4f7", "e8e7", "c3d5"]);
+        assert_eq!(puzzle.rating, 1300);
+    }
+
+    #[test]
+    fn several_wins_are_no_puzzle() {
+        //Kb6 and Kc7 both mate next move
+        let before = Position::from_fen("1k6/8/2K5/8/8/8/8/7R b - - 0 1").unwrap();
+        let opponent = Move::from_uci(&before, "b8a8").unwrap();
+        let mut position = before.clone();
+        make_move(&mut position, opponent);
+
+        let (best, second, _) = best_and_second(&position, MINE_TIME).unwrap();
+        assert!(best > WINNING && best - second < MINE_GAP);
+        assert!(find_puzzle(&before, opponent, &position, &mine_options()).is_none());
+    }
+}
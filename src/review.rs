@@ -0,0 +1,272 @@
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::engine::{self, Difficulty, Limits, Rng, MATE_SCORE};
+use crate::movegen::{make_move, outcome, Move, Outcome};
+use crate::pgn::{self, Annotated};
+use crate::position::Position;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "inaccuracy",
+            Judgement::Mistake => "mistake",
+            Judgement::Blunder => "blunder",
+        }
+    }
+
+    //Numeric annotation glyphs for ?!, ? and ??
+    fn nag(self) -> u8 {
+        match self {
+            Judgement::Inaccuracy => 6,
+            Judgement::Mistake => 2,
+            Judgement::Blunder => 4,
+        }
+    }
+}
+
+pub struct MoveReview {
+    pub mv: Move,
+    pub san: String,
+    pub team_white: bool,
+    //Centipawns from white's point of view before and after the move
+    pub before: i32,
+    pub after: i32,
+    //What the engine would have played, None when it agrees with the move
+    pub best: Option<(Move, String)>,
+    pub judgement: Option<Judgement>,
+    //0 to 100, how much of the mover's winning chances the move kept
+    pub accuracy: f64,
+}
+
+//Mates count as a big material lead so a slower mate isn't called a mistake
+pub const SCORE_CAP: i32 = 1000;
+
+//Chance of winning in percent for the side the score is for
+fn win_percent(score: i32) -> f64 {
+    let score = score.clamp(-SCORE_CAP, SCORE_CAP) as f64;
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * score).exp()) - 1.0)
+}
+
+//Thresholds are in percentage points of winning chances lost
+fn judge(lost: f64) -> Option<Judgement> {
+    if lost >= 15.0 {
+        Some(Judgement::Blunder)
+    } else if lost >= 10.0 {
+        Some(Judgement::Mistake)
+    } else if lost >= 5.0 {
+        Some(Judgement::Inaccuracy)
+    } else {
+        None
+    }
+}
+
+//White's point of view, with finished games scored by their result
+fn evaluate(position: &Position, time: Duration) -> (i32, Option<Move>) {
+    if let Some(outcome) = outcome(position) {
+        return (
+            match outcome {
+                Outcome::WhiteWins(_) => MATE_SCORE,
+                Outcome::BlackWins(_) => -MATE_SCORE,
+                Outcome::Draw(_) => 0,
+            },
+            None,
+        );
+    }
+
+    let result = engine::search(
+        position,
+        &Difficulty::from_level(engine::MAX_LEVEL),
+        &Limits {
+            time: Some(time),
+            ..Default::default()
+        },
+        &AtomicBool::new(false),
+        &mut Rng::new(),
+        &mut |_| {},
+    );
+    (result.score, result.best_move)
+}
+
+/*
+Searches every position of the game for the given time each. A move's worth is the score of the
+position it leads to against the score of the best move, so each position is searched only once.
+*/
+pub fn review(start: &Position, moves: &[Move], time: Duration) -> Vec<MoveReview> {
+    let mut position = start.clone();
+    let mut current = evaluate(&position, time);
+    let mut reviews = vec![];
+
+    for (i, &mv) in moves.iter().enumerate() {
+        println!("Reviewing move {} of {}", i + 1, moves.len());
+        let team_white = position.white_to_move;
+        let san = mv.to_san(&position);
+        let best = current
+            .1
+            .filter(|&x| x != mv)
+            .map(|x| (x, x.to_san(&position)));
+
+        make_move(&mut position, mv);
+        let next = evaluate(&position, time);
+
+        let sign = if team_white { 1 } else { -1 };
+        //Searches aren't exact, so playing the best move never counts against anyone
+        let lost = if best.is_some() {
+            (win_percent(current.0 * sign) - win_percent(next.0 * sign)).max(0.0)
+        } else {
+            0.0
+        };
+        let accuracy = (103.1668 * (-0.04354 * lost).exp() - 3.1669).clamp(0.0, 100.0);
+
+        reviews.push(MoveReview {
+            mv,
+            san,
+            team_white,
+            before: current.0,
+            after: next.0,
+            best,
+            judgement: judge(lost),
+            accuracy,
+        });
+        current = next;
+    }
+
+    reviews
+}
+
+fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("1 {word}"),
+        _ if word.ends_with('y') => format!("{count} {}ies", &word[..word.len() - 1]),
+        _ => format!("{count} {word}s"),
+    }
+}
+
+//Average accuracy of one side's moves with counts of each kind of error
+pub fn summary(reviews: &[MoveReview], team_white: bool) -> String {
+    let moves = reviews
+        .iter()
+        .filter(|x| x.team_white == team_white)
+        .collect::<Vec<&MoveReview>>();
+    let count = |judgement: Judgement| {
+        moves
+            .iter()
+            .filter(|x| x.judgement == Some(judgement))
+            .count()
+    };
+    let accuracy = if moves.is_empty() {
+        100.0
+    } else {
+        moves.iter().map(|x| x.accuracy).sum::<f64>() / moves.len() as f64
+    };
+
+    format!(
+        "{} accuracy {accuracy:.1}% ({}, {}, {})",
+        if team_white { "White" } else { "Black" },
+        plural(count(Judgement::Inaccuracy), "inaccuracy"),
+        plural(count(Judgement::Mistake), "mistake"),
+        plural(count(Judgement::Blunder), "blunder")
+    )
+}
+
+//Evaluations in the form PGN tools read from comments, pawns or mate in moves
+fn eval_comment(score: i32) -> String {
+    if score.abs() > MATE_SCORE - 1000 {
+        let moves = (MATE_SCORE - score.abs() + 1) / 2;
+        format!("[%eval #{}{moves}]", if score > 0 { "" } else { "-" })
+    } else {
+        format!("[%eval {:.2}]", score as f64 / 100.0)
+    }
+}
+
+//The game with each move's evaluation as a comment and its errors marked
+pub fn annotated_pgn(
+    tags: &[(String, String)],
+    start: &Position,
+    reviews: &[MoveReview],
+    result: &str,
+) -> String {
+    let moves = reviews
+        .iter()
+        .map(|x| {
+            //Mate on the board needs no evaluation
+            let mut comment = if x.after.abs() == MATE_SCORE {
+                String::new()
+            } else {
+                eval_comment(x.after)
+            };
+            if let (Some(judgement), Some((_, best))) = (x.judgement, &x.best) {
+                let name = judgement.name();
+                comment += &format!(
+                    "{}{}{}. {best} was best.",
+                    if comment.is_empty() { "" } else { " " },
+                    name[..1].to_uppercase(),
+                    &name[1..]
+                );
+            }
+            Annotated {
+                san: x.san.clone(),
+                nag: x.judgement.map(|x| x.nag()),
+                comment: (!comment.is_empty()).then_some(comment),
+            }
+        })
+        .collect::<Vec<Annotated>>();
+
+    pgn::write(tags, start, &moves, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winning_chances() {
+        assert_eq!(win_percent(0), 50.0);
+        assert!((win_percent(300) + win_percent(-300) - 100.0).abs() < 1e-9);
+        assert!(win_percent(300) > 75.0);
+        //Mates are capped so a quicker one isn't much better than a slower one
+        assert_eq!(win_percent(MATE_SCORE), win_percent(SCORE_CAP));
+    }
+
+    #[test]
+    fn thresholds() {
+        assert!(judge(4.9).is_none());
+        assert!(judge(5.0) == Some(Judgement::Inaccuracy));
+        assert!(judge(10.0) == Some(Judgement::Mistake));
+        assert!(judge(15.0) == Some(Judgement::Blunder));
+        assert!(judge(60.0) == Some(Judgement::Blunder));
+    }
+
+    #[test]
+    fn hanging_the_queen_is_a_blunder() {
+        let start = Position::from_fen("4k3/8/8/3r4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let time = Duration::from_millis(200);
+
+        let blunder = Move::from_uci(&start, "d1d4").unwrap();
+        let reviews = review(&start, &[blunder], time);
+        assert!(reviews[0].judgement == Some(Judgement::Blunder));
+        assert_eq!(reviews[0].best.as_ref().unwrap().1, "Qxd5");
+        assert!(reviews[0].accuracy < 10.0);
+        assert!(summary(&reviews, true).ends_with("(0 inaccuracies, 0 mistakes, 1 blunder)"));
+
+        let best = Move::from_uci(&start, "d1d5").unwrap();
+        let reviews = review(&start, &[best], time);
+        assert!(reviews[0].judgement.is_none());
+        assert!(reviews[0].best.is_none());
+        assert!(reviews[0].accuracy > 99.9);
+    }
+
+    #[test]
+    fn evaluation_comments() {
+        assert_eq!(eval_comment(-150), "[%eval -1.50]");
+        assert_eq!(eval_comment(MATE_SCORE - 1), "[%eval #1]");
+        assert_eq!(eval_comment(-(MATE_SCORE - 4)), "[%eval #-2]");
+    }
+}
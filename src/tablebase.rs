@@ -0,0 +1,1291 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::bitboard::{index_64, square_index, squares};
+use crate::engine::MATE_SCORE;
+use crate::movegen::{in_check, legal_moves, make_move, unmake_move, Move};
+use crate::position::Position;
+use crate::variant::Variant;
+use crate::PieceType;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+const WDL_SUFFIX: &str = "rtbw";
+const DTZ_SUFFIX: &str = "rtbz";
+
+//Flags at the start of each table in a file, all but the last one only used by DTZ tables
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+//Flags in the first byte of a file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+//Piece letters in the order table names list them
+const NAME_ORDER: [(char, PieceType); 6] = [
+    ('K', PieceType::King),
+    ('Q', PieceType::Queen),
+    ('R', PieceType::Rook),
+    ('B', PieceType::Bishop),
+    ('N', PieceType::Knight),
+    ('P', PieceType::Pawn),
+];
+
+//Tablebase scores sit below mates so searches and protocols keep them apart
+const WIN_SCORE: i32 = MATE_SCORE - 2000;
+
+/*
+Results the tables store. Cursed wins and blessed losses would be wins and losses but for the
+fifty move rule.
+*/
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            ..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn value(self) -> i32 {
+        self as i32 - 2
+    }
+
+    //The same result from the other side's point of view
+    pub fn flip(self) -> Wdl {
+        Wdl::from_value(-self.value())
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        }
+    }
+}
+
+//DTZ of a move that wins, draws or loses and resets the fifty move counter
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+    }
+}
+
+/*
+What a probe found, from the side to move's point of view. DTZ counts plies until the fifty move
+counter is reset by a capture, a pawn move or mate, negative when losing. It says nothing of how
+far away mate is, so it's given as the moves to that zeroing move.
+*/
+pub fn describe(wdl: Wdl, dtz: Option<i32>) -> String {
+    let moves = dtz.map_or(String::new(), |x| {
+        format!(
+            ", zeroing move (capture or pawn move) in {}",
+            (x.abs() + 1) / 2
+        )
+    });
+    match wdl {
+        Wdl::Win => format!("tablebase win{moves}"),
+        Wdl::Loss => format!("tablebase loss{moves}"),
+        Wdl::Draw => "tablebase draw".to_string(),
+        Wdl::CursedWin => {
+            "tablebase draw, the win comes too late for the fifty move rule".to_string()
+        }
+        Wdl::BlessedLoss => "tablebase draw, the loss is saved by the fifty move rule".to_string(),
+    }
+}
+
+//Centipawns for engine protocols, a shorter way to zeroing being better for the winner
+pub fn score(wdl: Wdl, dtz: i32) -> i32 {
+    match wdl {
+        Wdl::Win => WIN_SCORE - dtz.abs(),
+        Wdl::Loss => -WIN_SCORE + dtz.abs(),
+        _ => 0,
+    }
+}
+
+//Lookup tables used to turn piece placements into table indexes, shared by every table
+struct Indexes {
+    //Squares a2 to h7 ordered so the leading pawn, nearest the edge and lowest, scores highest
+    map_pawns: [u64; 64],
+    //Squares below the a1-h8 diagonal
+    map_b1h1h7: [u64; 64],
+    //The a1-d1-d4 triangle, diagonal squares last
+    map_a1d1d4: [u64; 64],
+    //The 462 legal ways to place both kings with the first in the triangle
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; 7],
+    lead_pawn_index: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+//Rank minus file, zero on the a1-h8 diagonal and negative below it
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn indexes() -> &'static Indexes {
+    static INDEXES: OnceLock<Indexes> = OnceLock::new();
+    INDEXES.get_or_init(|| {
+        let mut indexes = Indexes {
+            map_pawns: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 7],
+            lead_pawn_index: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        for (code, square) in (0..64).filter(|&x| off_diagonal(x) < 0).enumerate() {
+            indexes.map_b1h1h7[square] = code as u64;
+        }
+
+        let triangle = (0..=27).filter(|&x| x % 8 <= 3);
+        let (below, diagonal): (Vec<usize>, Vec<usize>) = triangle
+            .filter(|&x| off_diagonal(x) <= 0)
+            .partition(|&x| off_diagonal(x) < 0);
+        for (code, &square) in below.iter().chain(&diagonal).enumerate() {
+            indexes.map_a1d1d4[square] = code as u64;
+        }
+
+        let mut code = 0;
+        let mut both_on_diagonal = vec![];
+        for (index, &first) in below.iter().chain(&diagonal).enumerate() {
+            for second in 0..64 {
+                //Kings can't stand on or next to each other
+                if (first / 8).abs_diff(second / 8) <= 1 && (first % 8).abs_diff(second % 8) <= 1 {
+                    continue;
+                }
+                if off_diagonal(first) == 0 {
+                    if off_diagonal(second) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(second) == 0 {
+                        both_on_diagonal.push((index, second));
+                        continue;
+                    }
+                }
+                indexes.map_kk[index][second] = code;
+                code += 1;
+            }
+        }
+        for (index, second) in both_on_diagonal {
+            indexes.map_kk[index][second] = code;
+            code += 1;
+        }
+
+        indexes.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                indexes.binomial[k][n] = if k > 0 {
+                    indexes.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { indexes.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        let mut available: i32 = 47;
+        for count in 1..=5 {
+            for file in 0..4 {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if count == 1 {
+                        indexes.map_pawns[square] = available as u64;
+                        indexes.map_pawns[square ^ 7] = (available - 1) as u64;
+                        available -= 2;
+                    }
+                    indexes.lead_pawn_index[count][square] = index;
+                    index += indexes.binomial[count - 1][indexes.map_pawns[square] as usize];
+                }
+                indexes.lead_pawns_size[count][file] = index;
+            }
+        }
+
+        indexes
+    })
+}
+
+//Piece codes used in the files, black pieces having bit 3 set
+fn piece_code(team_white: bool, piece_type: PieceType) -> u8 {
+    let kind = match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+        //Never in the tables, covers() keeps them out
+        PieceType::Fairy(_) => 0,
+    };
+    if team_white {
+        kind
+    } else {
+        kind | 8
+    }
+}
+
+//One Huffman coded and pair compressed run of values, by leading pawn file and side to move
+#[derive(Default)]
+struct Pairs {
+    flags: u8,
+    min_symbol_length: u32,
+    block_size: usize,
+    //Each sparse index entry points into the block lengths about every span values
+    span: u64,
+    blocks: usize,
+    block_lengths_size: usize,
+    sparse_index_size: usize,
+    //Offsets into the file
+    lowest_symbol: usize,
+    tree: usize,
+    block_lengths: usize,
+    sparse_index: usize,
+    data: usize,
+    base: Vec<u64>,
+    //How many values minus one each symbol stands for
+    symbol_length: Vec<u32>,
+    //Pieces in the order they're encoded, which sets the groups
+    pieces: Vec<u8>,
+    group_index: Vec<u64>,
+    //Pieces in each group, ending with a zero
+    group_length: Vec<usize>,
+    //Where the DTZ value maps for wins, losses, cursed wins and blessed losses begin
+    map: [usize; 4],
+}
+
+struct Table {
+    data: Vec<u8>,
+    dtz: bool,
+    pawns: bool,
+    //Both sides have the same pieces, so only white to move is stored
+    symmetric: bool,
+    unique_pieces: bool,
+    //Pawns of the leading side, then the other side
+    pawn_counts: [usize; 2],
+    piece_count: usize,
+    //By file of the leading pawn, then side to move
+    pairs: Vec<Vec<Pairs>>,
+}
+
+enum Lookup {
+    Value(i32),
+    //DTZ tables only store one side to move, and this position isn't it
+    OtherSide,
+}
+
+impl Table {
+    /*
+    Reads past the end give zeros, so a damaged file runs to its end while the layout is read
+    and is turned down by the size check there.
+    */
+    fn bytes<const N: usize>(&self, at: usize) -> [u8; N] {
+        self.data
+            .get(at..at + N)
+            .map_or([0; N], |x| x.try_into().unwrap())
+    }
+
+    fn byte(&self, at: usize) -> u8 {
+        self.bytes::<1>(at)[0]
+    }
+
+    fn u16_le(&self, at: usize) -> u16 {
+        u16::from_le_bytes(self.bytes(at))
+    }
+
+    fn u32_le(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.bytes(at))
+    }
+
+    fn u32_be(&self, at: usize) -> u32 {
+        u32::from_be_bytes(self.bytes(at))
+    }
+
+    fn u64_be(&self, at: usize) -> u64 {
+        u64::from_be_bytes(self.bytes(at))
+    }
+
+    //Children of a pair symbol, packed into three bytes as two 12 bit numbers
+    fn tree_left(&self, pairs: &Pairs, symbol: usize) -> usize {
+        let at = pairs.tree + symbol * 3;
+        (self.byte(at + 1) as usize & 0xf) << 8 | self.byte(at) as usize
+    }
+
+    fn tree_right(&self, pairs: &Pairs, symbol: usize) -> usize {
+        let at = pairs.tree + symbol * 3;
+        (self.byte(at + 2) as usize) << 4 | (self.byte(at + 1) as usize) >> 4
+    }
+
+    /*
+    Reads a table for the material in the name, like KRvK. The first side in the name is white
+    in the table, the stronger side by the generator's reckoning.
+    */
+    fn load(path: &PathBuf, name: &str, dtz: bool) -> Result<Table, String> {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Could not read tablebase {}: {e}", path.display()))?;
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if data.len() < 5 || data[..4] != magic {
+            return Err(format!("{} is not a Syzygy table", path.display()));
+        }
+
+        let (white, black) = name
+            .split_once('v')
+            .ok_or(format!("Bad table name {name}"))?;
+        let count = |side: &str, letter: char| side.chars().filter(|&x| x == letter).count();
+        let pawns = [count(white, 'P'), count(black, 'P')];
+        //The side with fewer pawns leads when both have some, it compresses better
+        let white_leads = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+
+        let mut table = Table {
+            data,
+            dtz,
+            pawns: pawns[0] + pawns[1] > 0,
+            symmetric: white == black,
+            unique_pieces: "QRBNP"
+                .chars()
+                .any(|x| count(white, x) == 1 || count(black, x) == 1),
+            pawn_counts: if white_leads {
+                pawns
+            } else {
+                [pawns[1], pawns[0]]
+            },
+            piece_count: white.len() + black.len(),
+            pairs: vec![],
+        };
+
+        let flags = table.byte(4);
+        if (flags & HAS_PAWNS != 0) != table.pawns || (flags & SPLIT != 0) == table.symmetric {
+            return Err(format!("{} doesn't match its name", path.display()));
+        }
+
+        table.read_layout()?;
+        Ok(table)
+    }
+
+    fn sides(&self) -> usize {
+        if self.dtz || self.symmetric {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn files(&self) -> usize {
+        if self.pawns {
+            4
+        } else {
+            1
+        }
+    }
+
+    //Finds where everything is in the file, following the order the generator writes it in
+    fn read_layout(&mut self) -> Result<(), String> {
+        let mut at = 5;
+        let both_pawns = self.pawns && self.pawn_counts[1] > 0;
+        let mut all_pairs = vec![];
+
+        for file in 0..self.files() {
+            let order_byte = |x: usize| self.byte(x);
+            let orders = [
+                [
+                    order_byte(at) & 0xf,
+                    if both_pawns {
+                        order_byte(at + 1) & 0xf
+                    } else {
+                        0xf
+                    },
+                ],
+                [
+                    order_byte(at) >> 4,
+                    if both_pawns {
+                        order_byte(at + 1) >> 4
+                    } else {
+                        0xf
+                    },
+                ],
+            ];
+            at += 1 + both_pawns as usize;
+
+            let mut sides = vec![];
+            for (side, order) in orders.iter().enumerate().take(self.sides()) {
+                let pieces = (0..self.piece_count)
+                    .map(|k| {
+                        let byte = self.byte(at + k);
+                        if side == 1 {
+                            byte >> 4
+                        } else {
+                            byte & 0xf
+                        }
+                    })
+                    .collect::<Vec<u8>>();
+                let mut pairs = Pairs {
+                    pieces,
+                    ..Default::default()
+                };
+                self.set_groups(&mut pairs, order, file);
+                sides.push(pairs);
+            }
+            at += self.piece_count;
+            all_pairs.push(sides);
+        }
+        at += at & 1;
+
+        for pairs in all_pairs.iter_mut().flatten() {
+            at = self.set_sizes(pairs, at)?;
+        }
+
+        if self.dtz {
+            for pairs in all_pairs.iter_mut().flatten() {
+                if pairs.flags & MAPPED == 0 {
+                    continue;
+                }
+                for i in 0..4 {
+                    if pairs.flags & WIDE != 0 {
+                        at += at & 1;
+                        pairs.map[i] = at + 2;
+                        at += 2 * self.u16_le(at) as usize + 2;
+                    } else {
+                        pairs.map[i] = at + 1;
+                        at += self.byte(at) as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for pairs in all_pairs.iter_mut().flatten() {
+            pairs.sparse_index = at;
+            at += pairs.sparse_index_size * 6;
+        }
+        for pairs in all_pairs.iter_mut().flatten() {
+            pairs.block_lengths = at;
+            at += pairs.block_lengths_size * 2;
+        }
+        for pairs in all_pairs.iter_mut().flatten() {
+            at = (at + 0x3f) & !0x3f;
+            pairs.data = at;
+            at += pairs.blocks * pairs.block_size;
+        }
+
+        if at > self.data.len() {
+            return Err("Tablebase file is truncated".to_string());
+        }
+        self.pairs = all_pairs;
+        Ok(())
+    }
+
+    /*
+    Splits the pieces into groups of the same piece, the first group being the leading pawns or
+    the two or three pieces encoded together. Groups are encoded in the order the table gives,
+    each index multiplied by the number of ways the groups after it can be placed.
+    */
+    fn set_groups(&self, pairs: &mut Pairs, order: &[u8; 2], file: usize) {
+        let indexes = indexes();
+        let mut first_length: i32 = if self.pawns {
+            0
+        } else if self.unique_pieces {
+            3
+        } else {
+            2
+        };
+
+        let mut lengths = vec![1];
+        for i in 1..self.piece_count {
+            first_length -= 1;
+            if first_length > 0 || pairs.pieces[i] == pairs.pieces[i - 1] {
+                *lengths.last_mut().unwrap() += 1;
+            } else {
+                lengths.push(1);
+            }
+        }
+        let groups = lengths.len();
+        lengths.push(0);
+
+        let both_pawns = self.pawns && self.pawn_counts[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - lengths[0] - if both_pawns { lengths[1] } else { 0 };
+        let mut index = 1u64;
+        let mut group_index = vec![0; groups + 1];
+
+        let mut k = 0;
+        while next < groups || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                group_index[0] = index;
+                index *= if self.pawns {
+                    indexes.lead_pawns_size[lengths[0]][file]
+                } else if self.unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                group_index[1] = index;
+                index *= indexes.binomial[lengths[1]][48 - lengths[0]];
+            } else {
+                group_index[next] = index;
+                index *= indexes.binomial[lengths[next]][free_squares];
+                free_squares -= lengths[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        group_index[groups] = index;
+
+        pairs.group_length = lengths;
+        pairs.group_index = group_index;
+    }
+
+    //Reads the sizes and Huffman code of one run of values, returning where the next starts
+    fn set_sizes(&self, pairs: &mut Pairs, mut at: usize) -> Result<usize, String> {
+        pairs.flags = self.byte(at);
+        at += 1;
+
+        if pairs.flags & SINGLE_VALUE != 0 {
+            //The one value every position has is kept where the symbol length would be
+            pairs.min_symbol_length = self.byte(at) as u32;
+            pairs.span = 1;
+            return Ok(at + 1);
+        }
+
+        let end = pairs.group_length.iter().position(|&x| x == 0).unwrap();
+        let size = pairs.group_index[end];
+
+        if self.byte(at) > 32 || self.byte(at + 1) > 32 {
+            return Err("Tablebase file has bad block sizes".to_string());
+        }
+        pairs.block_size = 1 << self.byte(at);
+        pairs.span = 1 << self.byte(at + 1);
+        pairs.sparse_index_size = size.div_ceil(pairs.span) as usize;
+        let padding = self.byte(at + 2) as usize;
+        pairs.blocks = self.u32_le(at + 3) as usize;
+        //Padded so the sparse index can't point past the end
+        pairs.block_lengths_size = pairs.blocks + padding;
+        let max_symbol_length = self.byte(at + 7) as u32;
+        pairs.min_symbol_length = self.byte(at + 8) as u32;
+        at += 9;
+        if max_symbol_length < pairs.min_symbol_length || max_symbol_length > 64 {
+            return Err("Tablebase file has a bad Huffman code".to_string());
+        }
+
+        /*
+        Canonical Huffman code, longer symbols having lower values. base[i] is the lowest code of
+        length i plus the minimum, padded out to 64 bits, so a code's length is found by comparing
+        against it.
+        */
+        pairs.lowest_symbol = at;
+        let lengths = (max_symbol_length - pairs.min_symbol_length + 1) as usize;
+        let lowest = |i: usize| self.u16_le(at + i * 2) as u64;
+        pairs.base = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            pairs.base[i] = pairs.base[i + 1]
+                .wrapping_add(lowest(i))
+                .wrapping_sub(lowest(i + 1))
+                / 2;
+        }
+        for (i, base) in pairs.base.iter_mut().enumerate() {
+            *base = base
+                .checked_shl(64 - i as u32 - pairs.min_symbol_length)
+                .unwrap_or(0);
+        }
+        at += lengths * 2;
+
+        let symbols = self.u16_le(at) as usize;
+        at += 2;
+        pairs.tree = at;
+
+        //Pair symbols stand for both their halves, so count the values each one expands to
+        pairs.symbol_length = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                pairs.symbol_length[symbol] = self.symbol_length(pairs, symbol, &mut visited);
+            }
+        }
+
+        Ok(at + symbols * 3 + (symbols & 1))
+    }
+
+    fn symbol_length(&self, pairs: &mut Pairs, symbol: usize, visited: &mut [bool]) -> u32 {
+        visited[symbol] = true;
+        let right = self.tree_right(pairs, symbol);
+        //Symbols that stand for a single value have no right half
+        if right == 0xfff {
+            return 0;
+        }
+        let left = self.tree_left(pairs, symbol);
+
+        for half in [left, right] {
+            if half < visited.len() && !visited[half] {
+                pairs.symbol_length[half] = self.symbol_length(pairs, half, visited);
+            }
+        }
+        let length = |x: usize| pairs.symbol_length.get(x).copied().unwrap_or(0);
+        length(left) + length(right) + 1
+    }
+
+    /*
+    Value number index of a run. The sparse index gives a block near it, then the Huffman
+    symbols of the block are read until the one covering the index, which is expanded pair by
+    pair down to a single value.
+    */
+    fn decompress(&self, pairs: &Pairs, index: u64) -> i32 {
+        if pairs.flags & SINGLE_VALUE != 0 {
+            return pairs.min_symbol_length as i32;
+        }
+
+        let entry = pairs.sparse_index + (index / pairs.span) as usize * 6;
+        let mut block = self.u32_le(entry) as usize;
+        let mut offset = self.u16_le(entry + 4) as i64;
+        //Entries point at the middle of their span
+        offset += (index % pairs.span) as i64 - (pairs.span / 2) as i64;
+
+        let block_length = |x: usize| self.u16_le(pairs.block_lengths + x * 2) as i64;
+        while offset < 0 {
+            block -= 1;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+
+        let mut at = pairs.data + block * pairs.block_size;
+        let mut buffer = self.u64_be(at);
+        at += 8;
+        let mut buffer_size = 64;
+        let min = pairs.min_symbol_length;
+
+        let mut symbol = loop {
+            let mut length = 0;
+            while buffer < pairs.base[length] {
+                length += 1;
+            }
+            let symbol = ((buffer - pairs.base[length])
+                .checked_shr(64 - length as u32 - min)
+                .unwrap_or(0) as u16)
+                .wrapping_add(self.u16_le(pairs.lowest_symbol + length * 2))
+                as usize;
+
+            if offset < pairs.symbol_length[symbol] as i64 + 1 {
+                break symbol;
+            }
+            offset -= pairs.symbol_length[symbol] as i64 + 1;
+
+            let bits = length as u32 + min;
+            buffer = buffer.checked_shl(bits).unwrap_or(0);
+            buffer_size -= bits;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (self.u32_be(at) as u64) << (64 - buffer_size);
+                at += 4;
+            }
+        };
+
+        while pairs.symbol_length[symbol] != 0 {
+            let left = self.tree_left(pairs, symbol);
+            if offset < pairs.symbol_length[left] as i64 + 1 {
+                symbol = left;
+            } else {
+                offset -= pairs.symbol_length[left] as i64 + 1;
+                symbol = self.tree_right(pairs, symbol);
+            }
+        }
+
+        self.tree_left(pairs, symbol) as i32
+    }
+
+    /*
+    Turns the position into an index the way the generator does: colours swapped so the table's
+    white is the side named first, then squares mirrored so the leading piece or pawn lands in
+    the part of the board the table covers.
+    */
+    fn probe(&self, position: &Position, flipped: bool, wdl: Wdl) -> Lookup {
+        let indexes = indexes();
+        let black_to_move = !position.white_to_move;
+        let flip = flipped || (self.symmetric && black_to_move);
+        let flip_colour = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let side = (flip ^ black_to_move) as usize;
+
+        let mut squares_list = vec![];
+        let mut pieces = vec![];
+        let mut lead_pawns = 0;
+        let mut file = 0;
+
+        if self.pawns {
+            let lead = self.pairs[0][0].pieces[0];
+            lead_pawns = position
+                .board
+                .pieces((lead ^ flip_colour) & 8 == 0, PieceType::Pawn);
+            for square in squares(lead_pawns) {
+                squares_list.push(index_64(square) ^ flip_squares);
+                pieces.push(lead);
+            }
+
+            let leading = (0..squares_list.len())
+                .max_by_key(|&x| indexes.map_pawns[squares_list[x]])
+                .unwrap();
+            squares_list.swap(0, leading);
+            file = (squares_list[0] % 8).min(7 - squares_list[0] % 8);
+        }
+        let lead_count = squares_list.len();
+
+        let pairs = &self.pairs[file][side % self.sides()];
+        //Symmetric tables without pawns look the same from both sides
+        let both_sides = self.symmetric && !self.pawns;
+        if self.dtz && !both_sides && (pairs.flags & STM) as usize != side {
+            return Lookup::OtherSide;
+        }
+
+        for square in squares(position.board.occupied() ^ lead_pawns) {
+            let (team_white, piece_type) = position.board.piece_at(square).unwrap();
+            squares_list.push(index_64(square) ^ flip_squares);
+            pieces.push(piece_code(team_white, piece_type) ^ flip_colour);
+        }
+        let size = squares_list.len();
+
+        //Same order as the table lists the pieces in
+        for i in lead_count..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| pairs.pieces[i] == pieces[j]) {
+                pieces.swap(i, j);
+                squares_list.swap(i, j);
+            }
+        }
+
+        if squares_list[0] % 8 > 3 {
+            for square in squares_list.iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut index;
+        if self.pawns {
+            index = indexes.lead_pawn_index[lead_count][squares_list[0]];
+            squares_list[1..lead_count].sort_by_key(|&x| indexes.map_pawns[x]);
+            for (i, &square) in squares_list.iter().enumerate().take(lead_count).skip(1) {
+                index += indexes.binomial[i][indexes.map_pawns[square] as usize];
+            }
+        } else {
+            if squares_list[0] / 8 > 3 {
+                for square in squares_list.iter_mut() {
+                    *square ^= 56;
+                }
+            }
+
+            //The first piece off the diagonal is put below it
+            for i in 0..pairs.group_length[0] {
+                let off = off_diagonal(squares_list[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for square in squares_list[i..].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            index = if self.unique_pieces {
+                let s = [squares_list[0], squares_list[1], squares_list[2]];
+                let rank = |x: usize| (x / 8) as u64;
+                let adjust1 = (s[1] > s[0]) as u64;
+                let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+
+                if off_diagonal(s[0]) != 0 {
+                    (indexes.map_a1d1d4[s[0]] * 63 + (s[1] as u64 - adjust1)) * 62 + s[2] as u64
+                        - adjust2
+                } else if off_diagonal(s[1]) != 0 {
+                    (6 * 63 + rank(s[0]) * 28 + indexes.map_b1h1h7[s[1]]) * 62 + s[2] as u64
+                        - adjust2
+                } else if off_diagonal(s[2]) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s[0]) * 7 * 28
+                        + (rank(s[1]) - adjust1) * 28
+                        + indexes.map_b1h1h7[s[2]]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s[0]) * 7 * 6
+                        + (rank(s[1]) - adjust1) * 6
+                        + (rank(s[2]) - adjust2)
+                }
+            } else {
+                indexes.map_kk[indexes.map_a1d1d4[squares_list[0]] as usize][squares_list[1]]
+            };
+        }
+
+        //The other groups are each encoded as a combination of the squares still free
+        index *= pairs.group_index[0];
+        let mut start = pairs.group_length[0];
+        let mut remaining_pawns = self.pawns && self.pawn_counts[1] > 0;
+        let mut next = 1;
+        while pairs.group_length[next] != 0 {
+            let length = pairs.group_length[next];
+            squares_list[start..start + length].sort();
+
+            let mut n = 0;
+            for i in 0..length {
+                let square = squares_list[start + i];
+                let adjust = squares_list[..start]
+                    .iter()
+                    .filter(|&&x| square > x)
+                    .count();
+                n += indexes.binomial[i + 1][square - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+
+            remaining_pawns = false;
+            index += n * pairs.group_index[next];
+            start += length;
+            next += 1;
+        }
+
+        let value = self.decompress(pairs, index);
+        if !self.dtz {
+            return Lookup::Value(value - 2);
+        }
+        Lookup::Value(self.dtz_value(pairs, value, wdl))
+    }
+
+    //DTZ in plies, the tables often storing moves or squeezing values through a map
+    fn dtz_value(&self, pairs: &Pairs, mut value: i32, wdl: Wdl) -> i32 {
+        if pairs.flags & MAPPED != 0 {
+            let map = match wdl {
+                Wdl::Win | Wdl::Draw => pairs.map[0],
+                Wdl::Loss => pairs.map[1],
+                Wdl::CursedWin => pairs.map[2],
+                Wdl::BlessedLoss => pairs.map[3],
+            };
+            value = if pairs.flags & WIDE != 0 {
+                self.u16_le(map + value as usize * 2) as i32
+            } else {
+                self.byte(map + value as usize) as i32
+            };
+        }
+
+        if (wdl == Wdl::Win && pairs.flags & WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && pairs.flags & LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+        value + 1
+    }
+}
+
+//Name of the pieces of one side, like KRP
+fn side_name(position: &Position, team_white: bool) -> String {
+    NAME_ORDER
+        .iter()
+        .map(|&(letter, piece_type)| {
+            letter
+                .to_string()
+                .repeat(position.board.pieces(team_white, piece_type).count_ones() as usize)
+        })
+        .collect()
+}
+
+//Whether a move resets the fifty move counter, and whether it's a capture
+fn zeroing(position: &Position, mv: Move) -> (bool, bool) {
+    let pawn = position
+        .board
+        .piece_at(square_index(mv.from))
+        .is_some_and(|x| x.1 == PieceType::Pawn);
+    let capture = position.piece_at(mv.to).is_some() || (pawn && mv.from.0 != mv.to.0);
+    (capture || pawn, capture)
+}
+
+//Tables read so far by name and whether they're DTZ, None for ones that couldn't be read
+type Loaded = HashMap<(String, bool), Option<Arc<Table>>>;
+
+/*
+Syzygy endgame tables read from directories on disk. Files are only read the first time a
+position needs them.
+*/
+pub struct Tablebase {
+    wdl_files: HashMap<String, PathBuf>,
+    dtz_files: HashMap<String, PathBuf>,
+    //Most pieces, kings included, of any table found
+    pub largest: usize,
+    loaded: Mutex<Loaded>,
+}
+
+impl Tablebase {
+    //Directories are separated like in PATH
+    pub fn open(paths: &str) -> Result<Self, String> {
+        let mut tablebase = Tablebase {
+            wdl_files: HashMap::new(),
+            dtz_files: HashMap::new(),
+            largest: 0,
+            loaded: Mutex::new(HashMap::new()),
+        };
+
+        for dir in std::env::split_paths(paths) {
+            let entries = std::fs::read_dir(&dir).map_err(|e| {
+                format!("Could not read tablebase directory {}: {e}", dir.display())
+            })?;
+
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(stem), Some(extension)) = (
+                    path.file_stem().and_then(|x| x.to_str()),
+                    path.extension().and_then(|x| x.to_str()),
+                ) else {
+                    continue;
+                };
+                let valid = stem.split_once('v').is_some_and(|(white, black)| {
+                    [white, black]
+                        .iter()
+                        .all(|x| x.starts_with('K') && x.chars().all(|x| "KQRBNP".contains(x)))
+                });
+                if !valid {
+                    continue;
+                }
+
+                let (files, magic) = match extension {
+                    WDL_SUFFIX => (&mut tablebase.wdl_files, WDL_MAGIC),
+                    DTZ_SUFFIX => (&mut tablebase.dtz_files, DTZ_MAGIC),
+                    _ => continue,
+                };
+                //Other files that happen to share the name are left alone
+                let mut header = [0; 4];
+                let readable = std::fs::File::open(&path)
+                    .and_then(|mut x| std::io::Read::read_exact(&mut x, &mut header))
+                    .is_ok();
+                if !readable || header != magic {
+                    continue;
+                }
+
+                files.insert(stem.to_string(), path.clone());
+                tablebase.largest = tablebase.largest.max(stem.len() - 1);
+            }
+        }
+
+        if tablebase.wdl_files.is_empty() {
+            return Err(format!("No Syzygy tables found in {paths}"));
+        }
+        Ok(tablebase)
+    }
+
+    //Castling rights, other rules and boards aren't in the tables, and bigger positions have none
+    pub fn covers(&self, position: &Position) -> bool {
+        position.pieces.len() <= self.largest
+            && (position.width, position.height) == (8, 8)
+            && !position.castling.contains(&true)
+            && position.variant == Variant::Standard
+            && !position
+                .pieces
+                .iter()
+                .any(|x| matches!(x.piece_type, PieceType::Fairy(_)))
+    }
+
+    //The table for the position's material and whether its colours are the other way around
+    fn table(&self, position: &Position, dtz: bool) -> Option<(Arc<Table>, bool)> {
+        let white = side_name(position, true);
+        let black = side_name(position, false);
+        let files = if dtz {
+            &self.dtz_files
+        } else {
+            &self.wdl_files
+        };
+
+        let (name, flipped) = [
+            (format!("{white}v{black}"), false),
+            (format!("{black}v{white}"), true),
+        ]
+        .into_iter()
+        .find(|x| files.contains_key(&x.0))?;
+
+        let mut loaded = self.loaded.lock().unwrap();
+        let table = loaded
+            .entry((name.clone(), dtz))
+            //A table that turns out to be damaged counts as missing from then on
+            .or_insert_with(|| Table::load(&files[&name], &name, dtz).ok().map(Arc::new))
+            .clone()?;
+        Some((table, flipped))
+    }
+
+    fn probe_wdl_table(&self, position: &Position) -> Option<Wdl> {
+        if position.pieces.len() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let (table, flipped) = self.table(position, false)?;
+        match table.probe(position, flipped, Wdl::Draw) {
+            Lookup::Value(v) => Some(Wdl::from_value(v)),
+            Lookup::OtherSide => None,
+        }
+    }
+
+    /*
+    Tables don't know about en passant and may store anything for positions where capturing is
+    best, so captures are searched first. Also returns whether the best result comes from a move
+    that resets the fifty move counter, in which case the DTZ table can't be trusted.
+    */
+    fn search(&self, position: &mut Position, pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = legal_moves(position);
+        if moves.is_empty() {
+            let wdl = if in_check(position) {
+                Wdl::Loss
+            } else {
+                Wdl::Draw
+            };
+            return Some((wdl, false));
+        }
+
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for &mv in &moves {
+            let (zeroing, capture) = zeroing(position, mv);
+            if !(capture || pawn_moves && zeroing) {
+                continue;
+            }
+            searched += 1;
+
+            let undo = make_move(position, mv);
+            let value = self.search(position, false);
+            unmake_move(position, mv, undo);
+
+            let value = value?.0.flip();
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let every_move = searched == moves.len();
+        let value = if every_move {
+            best
+        } else {
+            self.probe_wdl_table(position)?
+        };
+
+        if best >= value {
+            Some((best, best > Wdl::Draw || every_move))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    fn probe_dtz_inner(&self, position: &mut Position) -> Option<i32> {
+        let (wdl, zeroing_best) = self.search(position, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        if legal_moves(position).is_empty() {
+            return Some(-1);
+        }
+
+        let (table, flipped) = self.table(position, true)?;
+        if let Lookup::Value(dtz) = table.probe(position, flipped, wdl) {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.value().signum());
+        }
+
+        //The table has the other side to move, so look one move ahead for the best DTZ
+        let mut best = i32::MAX;
+        for mv in legal_moves(position) {
+            let (zeroing, _) = zeroing(position, mv);
+            let undo = make_move(position, mv);
+            let dtz = if zeroing {
+                self.search(position, false)
+                    .map(|x| -dtz_before_zeroing(x.0))
+            } else {
+                self.probe_dtz_inner(position).map(|x| -x)
+            };
+            let mates = dtz == Some(1) && in_check(position) && legal_moves(position).is_empty();
+            unmake_move(position, mv, undo);
+
+            let mut dtz = dtz?;
+            if mates {
+                best = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < best && dtz.signum() == wdl.value().signum() {
+                best = dtz;
+            }
+        }
+
+        Some(if best == i32::MAX { -1 } else { best })
+    }
+
+    //WDL of the position for the side to move, None if there's no table for it
+    pub fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.search(&mut position.clone(), false).map(|x| x.0)
+    }
+
+    pub fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.probe_dtz_inner(&mut position.clone())
+    }
+
+    /*
+    Every legal move with its WDL and DTZ for the side playing it, best first: the quickest
+    wins, then draws, then the slowest losses. DTZ is None without a DTZ table.
+    */
+    pub fn rank_moves(&self, position: &Position) -> Option<Vec<(Move, Wdl, Option<i32>)>> {
+        if !self.covers(position) {
+            return None;
+        }
+
+        let mut position = position.clone();
+        let mut ranked = vec![];
+        for mv in legal_moves(&position) {
+            let undo = make_move(&mut position, mv);
+            let wdl = self.search(&mut position, false).map(|x| x.0.flip());
+            let dtz = if position.halfmove_clock == 0 {
+                wdl.map(dtz_before_zeroing)
+            } else {
+                self.probe_dtz_inner(&mut position).map(|x| -x - x.signum())
+            };
+            let mates = in_check(&position) && legal_moves(&position).is_empty();
+            unmake_move(&mut position, mv, undo);
+
+            ranked.push((mv, wdl?, if mates { Some(1) } else { dtz }));
+        }
+
+        //Losses have negative DTZ, so the lowest DTZ is best whatever the result
+        ranked.sort_by_key(|&(_, wdl, dtz)| (std::cmp::Reverse(wdl), dtz.unwrap_or(0)));
+        Some(ranked)
+    }
+
+    //The best move by the tables, only when DTZ is there to make progress with
+    pub fn best_move(&self, position: &Position) -> Option<(Move, Wdl, i32)> {
+        let ranked = self.rank_moves(position)?;
+        let &(mv, wdl, dtz) = ranked.first()?;
+        Some((mv, wdl, dtz?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Real tables are too big to keep with the crate, these tests run on the ones SYZYGY_PATH names
+    fn tables() -> Tablebase {
+        let path = std::env::var("SYZYGY_PATH")
+            .expect("SYZYGY_PATH has to name a directory with the 3 and 4 piece tables");
+        Tablebase::open(&path).unwrap()
+    }
+
+    fn position(fen: &str) -> Position {
+        Position::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn describe_gives_moves_to_zeroing() {
+        assert_eq!(
+            describe(Wdl::Win, Some(7)),
+            "tablebase win, zeroing move (capture or pawn move) in 4"
+        );
+        assert_eq!(
+            describe(Wdl::Loss, Some(-2)),
+            "tablebase loss, zeroing move (capture or pawn move) in 1"
+        );
+        assert_eq!(describe(Wdl::Win, None), "tablebase win");
+        assert_eq!(describe(Wdl::Draw, Some(0)), "tablebase draw");
+    }
+
+    #[test]
+    fn open_needs_real_tables() {
+        let dir = std::env::temp_dir().join(format!("chess-tablebase-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        //Named like a table, but without the header
+        std::fs::write(dir.join("KQvK.rtbw"), [0; 8]).unwrap();
+        let result = Tablebase::open(&dir.to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[ignore = "needs Syzygy tables in SYZYGY_PATH"]
+    fn queen_and_rook_mates() {
+        let tables = tables();
+        for (fen, mate) in [
+            ("7k/8/6K1/8/8/8/8/Q7 w - - 0 1", "a1a8"),
+            ("7k/8/6K1/8/8/8/8/R7 w - - 0 1", "a1a8"),
+        ] {
+            let won = position(fen);
+            assert_eq!(tables.probe_wdl(&won), Some(Wdl::Win), "{fen}");
+            assert_eq!(tables.probe_dtz(&won), Some(1), "{fen}");
+            let (mv, wdl, dtz) = tables.best_move(&won).unwrap();
+            assert_eq!(
+                (mv.to_uci().as_str(), wdl, dtz),
+                (mate, Wdl::Win, 1),
+                "{fen}"
+            );
+        }
+
+        //Black's only move leaves the mate on
+        let lost = position("7k/8/6K1/8/8/8/8/Q7 b - - 0 1");
+        assert_eq!(tables.probe_wdl(&lost), Some(Wdl::Loss));
+        assert!(tables.probe_dtz(&lost).is_some_and(|x| x < 0));
+    }
+
+    #[test]
+    #[ignore = "needs Syzygy tables in SYZYGY_PATH"]
+    fn pawn_endings() {
+        let tables = tables();
+        //The king on the sixth rank in front of its pawn wins whoever moves
+        let won = position("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1");
+        assert_eq!(tables.probe_wdl(&won), Some(Wdl::Win));
+        assert!(tables.probe_dtz(&won).is_some_and(|x| x > 0));
+        let lost = position("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1");
+        assert_eq!(tables.probe_wdl(&lost), Some(Wdl::Loss));
+        assert!(tables.probe_dtz(&lost).is_some_and(|x| x < 0));
+
+        //A rook pawn with the defending king in the corner is a draw
+        let drawn = position("7k/8/8/8/8/8/7P/7K w - - 0 1");
+        assert_eq!(tables.probe_wdl(&drawn), Some(Wdl::Draw));
+        assert_eq!(tables.probe_dtz(&drawn), Some(0));
+    }
+
+    #[test]
+    #[ignore = "needs Syzygy tables in SYZYGY_PATH"]
+    fn bishop_and_knight() {
+        let tables = tables();
+        let won = position("7k/8/6K1/8/8/8/8/BN6 w - - 0 1");
+        assert_eq!(tables.probe_wdl(&won), Some(Wdl::Win));
+        let ranked = tables.rank_moves(&won).unwrap();
+        assert_eq!(ranked[0].1, Wdl::Win);
+        assert!(ranked[0].2.is_some_and(|x| x > 0));
+    }
+}
@@ -0,0 +1,394 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::book::{Book, Pick};
+use crate::engine::{self, Difficulty, Limits, Rng, SearchInfo, MATE_SCORE};
+use crate::movegen::{legal_moves, make_move, perft, unmake_move, Move};
+use crate::position::Position;
+use crate::tablebase::{self, Tablebase};
+use crate::variant::{Variant, VARIANTS};
+
+struct Uci {
+    position: Position,
+    level: u8,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+    //Set while the search may not report its move yet, for go infinite and go ponder
+    infinite: Arc<AtomicBool>,
+    //go ponder searches until ponderhit, and then has the time it was given from there
+    pondering: bool,
+    ponder_time: Option<Duration>,
+    book: Option<Book>,
+    book_pick: Pick,
+    //OwnBook option, on by default when a book was given
+    own_book: bool,
+    tablebase: Option<Arc<Tablebase>>,
+    //UCI_Chess960, where castling is sent as the king taking its own rook
+    chess960: bool,
+    //UCI_Variant, the rules positions are played by
+    variant: Variant,
+}
+
+//Score as UCI wants it, mates counted in moves rather than plies
+pub fn format_score(score: i32) -> String {
+    if score > MATE_SCORE - 1000 {
+        format!("mate {}", (MATE_SCORE - score + 1) / 2)
+    } else if score < -MATE_SCORE + 1000 {
+        format!("mate -{}", (MATE_SCORE + score) / 2)
+    } else {
+        format!("cp {score}")
+    }
+}
+
+pub fn format_info(info: &SearchInfo) -> String {
+    let millis = info.time.as_millis() as u64;
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        format_score(info.score),
+        info.nodes,
+        info.nodes * 1000 / millis.max(1),
+        millis,
+        info.pv
+            .iter()
+            .map(|x| x.to_uci())
+            .collect::<Vec<String>>()
+            .join(" ")
+    )
+}
+
+//position [startpos | fen <fen>] [moves <move>...]
+fn parse_position(words: &[&str], chess960: bool, variant: Variant) -> Result<Position, String> {
+    let moves_index = words.iter().position(|&x| x == "moves");
+    let setup = &words[..moves_index.unwrap_or(words.len())];
+
+    let mut position = match setup.first() {
+        Some(&"startpos") => variant.start()?,
+        Some(&"fen") => Position::from_fen(&setup[1..].join(" "))?,
+        _ => return Err("position expects startpos or fen".to_string()),
+    };
+    position.chess960 |= chess960;
+    //Pockets or check counts in the FEN already say which variant it is
+    if position.variant == Variant::Standard {
+        position.variant = variant;
+    }
+
+    for text in moves_index.map_or(&[][..], |i| &words[i + 1..]) {
+        let mv = Move::from_uci(&position, text).ok_or(format!("Illegal move {text}"))?;
+        make_move(&mut position, mv);
+    }
+
+    Ok(position)
+}
+
+fn parse_limits(words: &[&str], position: &Position) -> (Limits, bool) {
+    let value = |name: &str| {
+        words
+            .iter()
+            .position(|&x| x == name)
+            .and_then(|i| words.get(i + 1))
+            .and_then(|x| x.parse::<i64>().ok())
+    };
+    let millis = |x: i64| Duration::from_millis(x.max(0) as u64);
+
+    let infinite = words.contains(&"infinite");
+
+    let clock = if position.white_to_move {
+        (value("wtime"), value("winc"))
+    } else {
+        (value("btime"), value("binc"))
+    };
+
+    let time = match (value("movetime"), clock) {
+        _ if infinite => None,
+        (Some(movetime), _) => Some(millis(movetime)),
+        (None, (Some(remaining), increment)) => Some(engine::time_for_move(
+            millis(remaining),
+            millis(increment.unwrap_or(0)),
+            value("movestogo").map_or(engine::DEFAULT_MOVES_TO_GO, |x| x.max(1) as u32),
+        )),
+        _ => None,
+    };
+
+    //searchmoves takes every move after it, up to the next word that isn't one
+    let moves = words
+        .iter()
+        .position(|&x| x == "searchmoves")
+        .map(|i| {
+            words[i + 1..]
+                .iter()
+                .map_while(|x| Move::from_uci(position, x))
+                .collect::<Vec<Move>>()
+        })
+        .filter(|x| !x.is_empty());
+
+    (
+        Limits {
+            depth: value("depth").map(|x| x.clamp(1, engine::MAX_DEPTH as i64) as u8),
+            nodes: value("nodes").map(|x| x.max(1) as u64),
+            time,
+            moves,
+        },
+        infinite,
+    )
+}
+
+impl Uci {
+    //Commands sent while searching wait for the result, so scripted sessions can be piped in
+    fn wait_for_search(&mut self) {
+        if self.infinite.load(Ordering::Relaxed) {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        if let Some(search) = self.search.take() {
+            search.join().unwrap();
+        }
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+    }
+
+    //go perft <depth> counts moves per root move, like most engines do
+    fn perft(&mut self, depth: u8) {
+        let mut total = 0;
+        for mv in legal_moves(&self.position) {
+            let undo = make_move(&mut self.position, mv);
+            let count = perft(&mut self.position, depth.saturating_sub(1));
+            unmake_move(&mut self.position, mv, undo);
+            println!("{}: {count}", mv.to_uci());
+            total += count;
+        }
+        println!("\nNodes searched: {total}");
+    }
+
+    fn go(&mut self, words: &[&str]) {
+        self.wait_for_search();
+
+        if words.first() == Some(&"perft") {
+            match words.get(1).and_then(|x| x.parse::<u8>().ok()) {
+                Some(depth) => self.perft(depth.max(1)),
+                None => println!("info string perft expects a depth"),
+            }
+            return;
+        }
+        self.stop = Arc::new(AtomicBool::new(false));
+
+        let (mut limits, infinite) = parse_limits(words, &self.position);
+        let allowed = |mv: &Move| limits.moves.as_ref().is_none_or(|x| x.contains(mv));
+        //Pondering is on the opponent's time, so the clock only counts once ponderhit comes
+        self.pondering = words.contains(&"ponder");
+        self.ponder_time = if self.pondering {
+            limits.time.take()
+        } else {
+            None
+        };
+        let infinite = infinite || self.pondering;
+        self.infinite = Arc::new(AtomicBool::new(infinite));
+
+        //Analysis still searches, only real moves come straight from the book
+        let book_move = self
+            .book
+            .as_ref()
+            .filter(|_| self.own_book && !infinite)
+            .and_then(|x| x.pick(&self.position, self.book_pick, &mut Rng::new()))
+            .filter(allowed);
+        if let Some(mv) = book_move {
+            println!("info string book move");
+            println!("bestmove {}", mv.to_uci());
+            return;
+        }
+
+        let tablebase_move = self
+            .tablebase
+            .as_ref()
+            .and_then(|x| x.best_move(&self.position))
+            .filter(|x| allowed(&x.0));
+        if let Some((mv, wdl, dtz)) = tablebase_move {
+            println!(
+                "info depth 1 score {} pv {}",
+                format_score(tablebase::score(wdl, dtz)),
+                mv.to_uci()
+            );
+            println!("info string {}", tablebase::describe(wdl, Some(dtz)));
+            if !infinite {
+                println!("bestmove {}", mv.to_uci());
+                return;
+            }
+        }
+        let position = self.position.clone();
+        let difficulty = Difficulty::from_level(self.level);
+        let stop = self.stop.clone();
+        let waiting = self.infinite.clone();
+
+        self.search = Some(std::thread::spawn(move || {
+            let result = engine::search(
+                &position,
+                &difficulty,
+                &limits,
+                &stop,
+                &mut Rng::new(),
+                &mut |info| println!("{}", format_info(info)),
+            );
+
+            //An infinite search only reports its move once told to stop
+            while waiting.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(5));
+            }
+
+            match result.best_move {
+                Some(v) => println!("bestmove {}", v.to_uci()),
+                None => println!("bestmove 0000"),
+            }
+        }));
+    }
+
+    //The opponent played the move pondered on, so the search goes on as an ordinary one
+    fn ponder_hit(&mut self) {
+        if !std::mem::take(&mut self.pondering) {
+            return;
+        }
+        if let Some(time) = self.ponder_time.take() {
+            let stop = self.stop.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(time);
+                stop.store(true, Ordering::Relaxed);
+            });
+        }
+        self.infinite.store(false, Ordering::Relaxed);
+    }
+
+    //setoption name <name> [value <value>], anything else is ignored
+    fn set_option(&mut self, words: &[&str]) {
+        let value_index = words
+            .iter()
+            .position(|&x| x == "value")
+            .unwrap_or(words.len());
+        if words.first() != Some(&"name") || value_index < 2 {
+            return;
+        }
+        let name = words[1..value_index].join(" ");
+        let value = words.get(value_index + 1..).unwrap_or(&[]).join(" ");
+
+        if name.eq_ignore_ascii_case("ownbook") {
+            self.own_book = value.eq_ignore_ascii_case("true");
+        } else if name.eq_ignore_ascii_case("syzygypath") {
+            //GUIs send <empty> to turn the tables off
+            self.tablebase = None;
+            if !value.is_empty() && value != "<empty>" {
+                match Tablebase::open(&value) {
+                    Ok(v) => {
+                        println!("info string Found tables with up to {} pieces", v.largest);
+                        self.tablebase = Some(Arc::new(v));
+                    }
+                    Err(e) => println!("info string {e}"),
+                }
+            }
+        } else if name.eq_ignore_ascii_case("uci_chess960") {
+            self.chess960 = value.eq_ignore_ascii_case("true");
+        } else if name.eq_ignore_ascii_case("uci_variant") {
+            //Starting it also loads the pieces the variant needs
+            match Variant::parse(&value).map(|x| (x, x.start())) {
+                Some((v, Ok(_))) => self.variant = v,
+                Some((_, Err(e))) => println!("info string {e}"),
+                None => println!("info string Unknown variant {value}"),
+            }
+        } else if name.eq_ignore_ascii_case("skill level") {
+            match value.parse::<u8>() {
+                Ok(v) if (engine::MIN_LEVEL..=engine::MAX_LEVEL).contains(&v) => self.level = v,
+                _ => println!("info string Skill Level must be between 1 and 10"),
+            }
+        } else {
+            println!("info string Unknown option {name}");
+        }
+    }
+}
+
+pub fn run(level: u8, book: Option<Book>, book_pick: Pick, tablebase: Option<Arc<Tablebase>>) {
+    let mut uci = Uci {
+        position: Position::startpos(),
+        level,
+        stop: Arc::new(AtomicBool::new(false)),
+        search: None,
+        infinite: Arc::new(AtomicBool::new(false)),
+        pondering: false,
+        ponder_time: None,
+        own_book: book.is_some(),
+        book,
+        book_pick,
+        tablebase,
+        chess960: false,
+        variant: Variant::Standard,
+    };
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+
+        match words.first().copied() {
+            Some("uci") => {
+                println!("id name chess");
+                println!("id author Silver167");
+                println!(
+                    "option name Skill Level type spin default {level} min {} max {}",
+                    engine::MIN_LEVEL,
+                    engine::MAX_LEVEL
+                );
+                println!("option name OwnBook type check default {}", uci.own_book);
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name UCI_Chess960 type check default false");
+                println!(
+                    "option name UCI_Variant type combo default chess{}",
+                    VARIANTS
+                        .iter()
+                        .map(|x| format!(" var {}", x.name()))
+                        .collect::<String>()
+                );
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("ucinewgame") => {
+                uci.wait_for_search();
+                match uci.variant.start() {
+                    Ok(v) => uci.position = v,
+                    Err(e) => println!("info string {e}"),
+                }
+                uci.position.chess960 = uci.chess960;
+            }
+            Some("position") => {
+                uci.wait_for_search();
+                match parse_position(&words[1..], uci.chess960, uci.variant) {
+                    Ok(v) => uci.position = v,
+                    Err(e) => println!("info string {e}"),
+                }
+            }
+            Some("go") => uci.go(&words[1..]),
+            Some("stop") => uci.stop_search(),
+            Some("ponderhit") => uci.ponder_hit(),
+            Some("setoption") => {
+                uci.wait_for_search();
+                uci.set_option(&words[1..]);
+            }
+            Some("quit") => {
+                uci.stop_search();
+                return;
+            }
+            //Not part of UCI but handy when driving the engine by hand
+            Some("d") => {
+                uci.wait_for_search();
+                println!("{}", crate::render_board(&uci.position));
+                println!("Fen: {}", uci.position.to_fen());
+                println!("Key: {:016X}", uci.position.hash);
+            }
+            //Anything unknown is ignored as the protocol asks
+            _ => {}
+        }
+    }
+
+    uci.wait_for_search();
+}
@@ -0,0 +1,255 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::engine::MATE_SCORE;
+use crate::game::Game;
+use crate::movegen::{make_move, Move};
+use crate::position::Position;
+use crate::variant::Variant;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+//Extra time an engine gets past its move time before it's considered hung
+const GRACE_TIME: Duration = Duration::from_secs(2);
+
+//An engine running in its own process, spoken to over UCI
+pub struct UciEngine {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    //Filled by a reader thread so waiting on the engine can time out
+    lines: Receiver<String>,
+    //Whether the engine was last told to play Chess960
+    chess960: bool,
+    //Rules the engine was last told to play by
+    variant: Variant,
+    //Start and moves last sent, a game that doesn't carry on from them is a new one
+    sent: Option<(String, Vec<Move>)>,
+}
+
+pub struct AnalysisLine {
+    pub depth: u32,
+    //Centipawns from the side to move's point of view, mates scored like the built-in engine
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+//Reads the parts of an info line worth showing, None for lines without a score and pv
+pub fn parse_info(position: &Position, line: &str) -> Option<AnalysisLine> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let value = |name: &str| {
+        words
+            .iter()
+            .position(|&x| x == name)
+            .and_then(|i| words.get(i + 1))
+    };
+
+    let score_index = words.iter().position(|&x| x == "score")?;
+    let amount = words.get(score_index + 2)?.parse::<i32>().ok()?;
+    let score = match *words.get(score_index + 1)? {
+        "cp" => amount,
+        //Mate in n moves is 2n - 1 plies away, being mated in n is 2n plies away
+        "mate" if amount > 0 => MATE_SCORE - (amount * 2 - 1),
+        "mate" => -MATE_SCORE - amount * 2,
+        _ => return None,
+    };
+
+    let pv_index = words.iter().position(|&x| x == "pv")?;
+    let mut pv = vec![];
+    let mut current = position.clone();
+    for text in &words[pv_index + 1..] {
+        let Some(mv) = Move::from_uci(&current, text) else {
+            break;
+        };
+        make_move(&mut current, mv);
+        pv.push(mv);
+    }
+
+    Some(AnalysisLine {
+        depth: value("depth")?.parse().ok()?,
+        score,
+        pv,
+    })
+}
+
+impl UciEngine {
+    pub fn start(path: &str) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not start {path}: {e}"))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            name: path.to_string(),
+            child,
+            stdin,
+            lines,
+            chess960: false,
+            variant: Variant::Standard,
+            sent: None,
+        };
+
+        engine.send("uci")?;
+        let mut name = None;
+        engine.wait_for("uciok", STARTUP_TIMEOUT, &mut |line| {
+            if let Some(v) = line.strip_prefix("id name ") {
+                name = Some(v.trim().to_string());
+            }
+        })?;
+        if let Some(v) = name {
+            engine.name = v;
+        }
+
+        engine.send("isready")?;
+        engine.wait_for("readyok", STARTUP_TIMEOUT, &mut |_| {})?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{command}")
+            .and_then(|_| self.stdin.flush())
+            .map_err(|_| format!("{} has stopped", self.name))
+    }
+
+    //Hands every line to on_line until one starts with the expected word
+    fn wait_for(
+        &mut self,
+        word: &str,
+        timeout: Duration,
+        on_line: &mut dyn FnMut(&str),
+    ) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self
+                .lines
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => {
+                    on_line(&line);
+                    if line.split_whitespace().next() == Some(word) {
+                        break Ok(line);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    break Err(format!("{} did not answer in time", self.name))
+                }
+                Err(RecvTimeoutError::Disconnected) => break Err(format!("{} crashed", self.name)),
+            }
+        }
+    }
+
+    /*
+    Searches the game's position for the given time, returning the bestmove line. The whole game
+    is sent so the engine knows about repetitions and the fifty move rule.
+    */
+    fn search(
+        &mut self,
+        game: &Game,
+        time: Duration,
+        on_info: &mut dyn FnMut(&str),
+    ) -> Result<String, String> {
+        let position = &game.start;
+        if position.chess960 != self.chess960 {
+            self.send(&format!(
+                "setoption name UCI_Chess960 value {}",
+                position.chess960
+            ))?;
+            self.chess960 = position.chess960;
+        }
+        if position.variant != self.variant {
+            self.send(&format!(
+                "setoption name UCI_Variant value {}",
+                position.variant.name()
+            ))?;
+            self.variant = position.variant;
+        }
+
+        let start = position.to_fen();
+        let continued = self
+            .sent
+            .as_ref()
+            .is_some_and(|(fen, moves)| *fen == start && game.moves.starts_with(moves));
+        if !continued {
+            self.send("ucinewgame")?;
+            self.send("isready")?;
+            self.wait_for("readyok", STARTUP_TIMEOUT, &mut |_| {})?;
+        }
+        self.sent = Some((start.clone(), game.moves.clone()));
+
+        let moves = game
+            .moves
+            .iter()
+            .map(|x| format!(" {}", x.to_uci()))
+            .collect::<String>();
+        if moves.is_empty() {
+            self.send(&format!("position fen {start}"))?;
+        } else {
+            self.send(&format!("position fen {start} moves{moves}"))?;
+        }
+        self.send(&format!("go movetime {}", time.as_millis()))?;
+
+        match self.wait_for("bestmove", time + GRACE_TIME, on_info) {
+            //Give a slow engine one last chance to answer before giving up on it
+            Err(_) if self.child.try_wait().is_ok_and(|x| x.is_none()) => {
+                self.send("stop")?;
+                self.wait_for("bestmove", GRACE_TIME, on_info)
+            }
+            result => result,
+        }
+    }
+
+    pub fn best_move(
+        &mut self,
+        game: &Game,
+        time: Duration,
+        on_info: &mut dyn FnMut(&str),
+    ) -> Result<Move, String> {
+        let line = self.search(game, time, on_info)?;
+        let text = line.split_whitespace().nth(1).unwrap_or("");
+
+        Move::from_uci(&game.position, text)
+            .ok_or(format!("{} played an illegal move {text}", self.name))
+    }
+
+    pub fn analyse(
+        &mut self,
+        game: &Game,
+        time: Duration,
+        on_line: &mut dyn FnMut(AnalysisLine),
+    ) -> Result<(), String> {
+        self.search(game, time, &mut |line| {
+            if let Some(v) = parse_info(&game.position, line) {
+                on_line(v);
+            }
+        })
+        .map(|_| ())
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
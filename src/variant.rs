@@ -0,0 +1,511 @@
+use crate::bitboard::{bit, index_square, king_attacks, squares, Bitboards};
+use crate::fairy;
+use crate::movegen::{in_check, legal_moves, make_move, Move, Outcome};
+use crate::position::{Position, START_FEN};
+use crate::zobrist;
+use crate::{Piece, PieceType};
+
+//Rule sets besides standard chess, kept with the position so every part of the program follows them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Variant {
+    Standard,
+    KingOfTheHill,
+    ThreeCheck,
+    RacingKings,
+    Antichess,
+    Horde,
+    Crazyhouse,
+    Atomic,
+    Capablanca,
+    Gardner,
+    LosAlamos,
+}
+
+pub const VARIANTS: [Variant; 11] = [
+    Variant::Standard,
+    Variant::KingOfTheHill,
+    Variant::ThreeCheck,
+    Variant::RacingKings,
+    Variant::Antichess,
+    Variant::Horde,
+    Variant::Crazyhouse,
+    Variant::Atomic,
+    Variant::Capablanca,
+    Variant::Gardner,
+    Variant::LosAlamos,
+];
+
+//What a crazyhouse pocket can hold, in the order of Position::pockets
+pub const POCKET: [PieceType; 5] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+];
+
+pub fn pocket_index(piece_type: PieceType) -> Option<usize> {
+    POCKET.iter().position(|&x| x == piece_type)
+}
+
+//d4, e4, d5 and e5
+const HILL: [(u8, u8); 4] = [(3, 4), (4, 4), (3, 3), (4, 3)];
+
+impl Variant {
+    //Names as typed after --variant or sent by GUIs, ignoring case, spaces and dashes
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.to_lowercase().replace([' ', '-', '_'], "");
+        match name.as_str() {
+            "standard" | "chess" | "normal" => Some(Variant::Standard),
+            "kingofthehill" | "koth" => Some(Variant::KingOfTheHill),
+            "threecheck" | "3check" => Some(Variant::ThreeCheck),
+            "racingkings" => Some(Variant::RacingKings),
+            "antichess" | "giveaway" => Some(Variant::Antichess),
+            "horde" => Some(Variant::Horde),
+            "crazyhouse" | "zh" => Some(Variant::Crazyhouse),
+            "atomic" => Some(Variant::Atomic),
+            "capablanca" | "capa" => Some(Variant::Capablanca),
+            "gardner" | "minichess" => Some(Variant::Gardner),
+            "losalamos" => Some(Variant::LosAlamos),
+            _ => None,
+        }
+    }
+
+    //Short name for the command line, save files and UCI_Variant
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::ThreeCheck => "3check",
+            Variant::RacingKings => "racingkings",
+            Variant::Antichess => "antichess",
+            Variant::Horde => "horde",
+            Variant::Crazyhouse => "crazyhouse",
+            Variant::Atomic => "atomic",
+            Variant::Capablanca => "capablanca",
+            Variant::Gardner => "gardner",
+            Variant::LosAlamos => "losalamos",
+        }
+    }
+
+    //As written in the PGN Variant tag
+    pub fn title(self) -> &'static str {
+        match self {
+            Variant::Standard => "Standard",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::RacingKings => "Racing Kings",
+            Variant::Antichess => "Antichess",
+            Variant::Horde => "Horde",
+            Variant::Crazyhouse => "Crazyhouse",
+            Variant::Atomic => "Atomic",
+            Variant::Capablanca => "Capablanca",
+            Variant::Gardner => "Gardner",
+            Variant::LosAlamos => "Los Alamos",
+        }
+    }
+
+    //XBoard has its own names for some of them
+    pub fn xboard_name(self) -> &'static str {
+        match self {
+            Variant::Standard => "normal",
+            Variant::Antichess => "giveaway",
+            _ => self.name(),
+        }
+    }
+
+    //How the game is won besides checkmate, shown when it starts
+    pub fn goal(self) -> &'static str {
+        match self {
+            Variant::Standard => "Checkmate the enemy king",
+            Variant::KingOfTheHill => {
+                "Checkmate, or bring your king to one of the four centre squares"
+            }
+            Variant::ThreeCheck => "Checkmate, or give check three times",
+            Variant::RacingKings => {
+                "Be first to bring your king to the eighth rank, giving check is not allowed"
+            }
+            Variant::Antichess => {
+                "Lose all your pieces or run out of moves, captures are compulsory"
+            }
+            Variant::Horde => "White checkmates, black takes every last white pawn",
+            Variant::Crazyhouse => {
+                "Checkmate, taken pieces change sides and can be dropped back in, e.g. N@F3"
+            }
+            Variant::Atomic => {
+                "Blow up or checkmate the enemy king, captures explode nearby pieces but pawns"
+            }
+            Variant::Capablanca => {
+                "Checkmate on a 10x8 board, with an archbishop (A) and chancellor (C) each"
+            }
+            Variant::Gardner => "Checkmate on a 5x5 board, pawns only step one square",
+            Variant::LosAlamos => {
+                "Checkmate on a 6x6 board without bishops, castling or pawns stepping two"
+            }
+        }
+    }
+
+    pub fn start_fen(self) -> &'static str {
+        match self {
+            Variant::RacingKings => "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1",
+            Variant::Antichess => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            Variant::Horde => {
+                "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+            }
+            Variant::ThreeCheck => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1",
+            Variant::Crazyhouse => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1",
+            Variant::Capablanca => {
+                "rnabqkbcnr/pppppppppp/10/10/10/10/PPPPPPPPPP/RNABQKBCNR w KQkq - 0 1"
+            }
+            Variant::Gardner => "rnbqk/ppppp/5/PPPPP/RNBQK w - - 0 1",
+            Variant::LosAlamos => "rnqknr/pppppp/6/6/PPPPPP/RNQKNR w - - 0 1",
+            _ => START_FEN,
+        }
+    }
+
+    //Capablanca's pieces have to be there before any of its positions can be read
+    pub fn load_pieces(self) {
+        if self == Variant::Capablanca {
+            fairy::load_capablanca();
+        }
+    }
+
+    //Fails when a pieces file lacks the variant's pieces
+    pub fn start(self) -> Result<Position, String> {
+        self.load_pieces();
+        let mut position = Position::from_fen(self.start_fen())
+            .map_err(|e| format!("Can't play {}: {e}", self.title()))?;
+        position.variant = self;
+        Ok(position)
+    }
+
+    //Whether the king has to be kept out of check and may castle
+    pub fn royal_king(self) -> bool {
+        self != Variant::Antichess
+    }
+
+    //Other variants can still be won with a lone king or a few minor pieces
+    pub fn material_draws(self) -> bool {
+        matches!(
+            self,
+            Variant::Standard | Variant::Capablanca | Variant::Gardner | Variant::LosAlamos
+        )
+    }
+
+    /*
+    Los Alamos has no bishops to promote to. Added pieces stay loaded for the whole run, so they're
+    only promoted to in Capablanca chess or when they came from a pieces file.
+    */
+    pub fn promotes_to(self, piece_type: PieceType) -> bool {
+        match piece_type {
+            PieceType::Bishop => self != Variant::LosAlamos,
+            PieceType::Fairy(_) => self == Variant::Capablanca || fairy::from_file(),
+            _ => true,
+        }
+    }
+
+    //Pawns step two from their second rank on boards of eight ranks, horde's from the first as well
+    pub fn double_step(self, team_white: bool, rank: usize, height: u8) -> bool {
+        let start_rank = if team_white { 1 } else { 6 };
+        (height == 8 && rank == start_rank) || (self == Variant::Horde && team_white && rank == 0)
+    }
+
+    //Atomic kings can't take, as they would blow themselves up
+    pub fn king_takes(self) -> bool {
+        self != Variant::Atomic
+    }
+
+    /*
+    In atomic a blown up king is the worst that can happen, and kings side by side can't be checked
+    as taking one would blow up the other.
+    */
+    pub fn king_attacked(self, board: &Bitboards, team_white: bool) -> bool {
+        let Some(king) = board.king(team_white) else {
+            return self == Variant::Atomic;
+        };
+        if self == Variant::Atomic {
+            match board.king(!team_white) {
+                None => return false,
+                Some(v) if king_attacks(king) & 1 << v != 0 => return false,
+                _ => {}
+            }
+        }
+        board.is_attacked(king, !team_white)
+    }
+
+    /*
+    Squares of the pieces blown up by a capture on the square, with the taker already there. Atomic
+    takes the taker and every piece but pawns around it, other variants nothing.
+    */
+    pub fn explosion(self, board: &Bitboards, square: usize) -> u128 {
+        if self != Variant::Atomic {
+            return 0;
+        }
+        let pawns = board.pieces(true, PieceType::Pawn) | board.pieces(false, PieceType::Pawn);
+        king_attacks(square) & board.occupied() & !pawns | 1 << square
+    }
+}
+
+/*
+What the move takes, en passant included. Castling in Chess960 is written as the king taking its
+own rook, which takes nothing.
+*/
+pub fn captured(position: &Position, mv: Move) -> Option<PieceType> {
+    if let Some(piece) = position.piece_at(mv.to) {
+        return (piece.team_white != position.white_to_move).then_some(piece.piece_type);
+    }
+    let en_passant = Some(mv.to) == position.en_passant
+        && mv.from.0 != mv.to.0
+        && position
+            .piece_at(mv.from)
+            .is_some_and(|x| x.piece_type == PieceType::Pawn);
+    en_passant.then_some(PieceType::Pawn)
+}
+
+pub fn is_capture(position: &Position, mv: Move) -> bool {
+    captured(position, mv).is_some()
+}
+
+//Pieces in a crazyhouse pocket go on any empty square, but pawns not on the first or last row
+pub fn drop_moves(position: &Position) -> Vec<Move> {
+    if position.variant != Variant::Crazyhouse {
+        return vec![];
+    }
+
+    let pocket = position.pockets[!position.white_to_move as usize];
+    let empty = !position.board.occupied() & position.area();
+    let mut moves = vec![];
+    for (i, piece_type) in POCKET.into_iter().enumerate() {
+        if pocket[i] == 0 {
+            continue;
+        }
+        for to in squares(empty).map(index_square) {
+            if piece_type != PieceType::Pawn || (to.1 != 0 && to.1 != 7) {
+                moves.push(Move {
+                    from: to,
+                    to,
+                    promotion: None,
+                    drop: Some(piece_type),
+                });
+            }
+        }
+    }
+    moves
+}
+
+/*
+Crazyhouse captures change sides and go to the taker's pocket, promoted pieces as the pawns they
+were. Promoted pieces are followed around the board for that. Called by make_move once the pieces
+have moved, the struck square being where the captured piece stood. Returns the change to the hash.
+*/
+pub fn fill_pockets(
+    position: &mut Position,
+    mv: Move,
+    captured: Option<&Piece>,
+    struck: (u8, u8),
+) -> u64 {
+    if position.variant != Variant::Crazyhouse {
+        return 0;
+    }
+
+    let team = !position.white_to_move as usize;
+    let was_promoted = position.promoted & bit(struck) != 0;
+    let before = zobrist::pockets_key(&position.pockets);
+
+    if let Some(index) = mv.drop.and_then(pocket_index) {
+        position.pockets[team][index] -= 1;
+    }
+    if let Some(piece) = captured {
+        let kind = if was_promoted {
+            PieceType::Pawn
+        } else {
+            piece.piece_type
+        };
+        if let Some(index) = pocket_index(kind) {
+            position.pockets[team][index] += 1;
+        }
+    }
+
+    position.promoted &= !bit(struck);
+    if position.promoted & bit(mv.from) != 0 {
+        position.promoted ^= bit(mv.from) | bit(mv.to);
+    }
+    if mv.promotion.is_some() {
+        position.promoted |= bit(mv.to);
+    }
+
+    before ^ zobrist::pockets_key(&position.pockets)
+}
+
+//Moves the variant rules out of what would be legal in standard chess
+pub fn restrict_moves(position: &Position, moves: Vec<Move>) -> Vec<Move> {
+    match position.variant {
+        //Taking is compulsory when anything can be taken
+        Variant::Antichess if moves.iter().any(|&x| is_capture(position, x)) => moves
+            .into_iter()
+            .filter(|&x| is_capture(position, x))
+            .collect(),
+        //Neither side may give check
+        Variant::RacingKings => moves
+            .into_iter()
+            .filter(|&x| {
+                let mut after = position.clone();
+                make_move(&mut after, x);
+                !in_check(&after)
+            })
+            .collect(),
+        _ => moves,
+    }
+}
+
+fn king_on(position: &Position, team_white: bool, squares: &[(u8, u8)]) -> bool {
+    position.pieces.iter().any(|x| {
+        x.team_white == team_white
+            && x.piece_type == PieceType::King
+            && squares.contains(&x.position)
+    })
+}
+
+//Wins and draws the variant adds, checked before running out of moves and the usual draws
+pub fn outcome(position: &Position) -> Option<Outcome> {
+    let has_pieces = |team_white: bool| position.pieces.iter().any(|x| x.team_white == team_white);
+
+    match position.variant {
+        Variant::Standard | Variant::Capablanca | Variant::Gardner | Variant::LosAlamos => None,
+        Variant::KingOfTheHill => {
+            if king_on(position, true, &HILL) {
+                Some(Outcome::WhiteWins("King of the hill"))
+            } else if king_on(position, false, &HILL) {
+                Some(Outcome::BlackWins("King of the hill"))
+            } else {
+                None
+            }
+        }
+        Variant::ThreeCheck => {
+            if position.checks[0] >= 3 {
+                Some(Outcome::WhiteWins("Third check"))
+            } else if position.checks[1] >= 3 {
+                Some(Outcome::BlackWins("Third check"))
+            } else {
+                None
+            }
+        }
+        Variant::RacingKings => {
+            let goal = (0..8).map(|x| (x, 0)).collect::<Vec<(u8, u8)>>();
+            match (
+                king_on(position, true, &goal),
+                king_on(position, false, &goal),
+            ) {
+                (true, true) => Some(Outcome::Draw("Both kings reached the eighth rank")),
+                (false, true) => Some(Outcome::BlackWins("King reached the eighth rank")),
+                //White got there first, black has one move to draw level
+                (true, false) => {
+                    let catches_up = !position.white_to_move
+                        && legal_moves(position).iter().any(|x| {
+                            x.to.1 == 0
+                                && position
+                                    .piece_at(x.from)
+                                    .is_some_and(|x| x.piece_type == PieceType::King)
+                        });
+                    (!catches_up).then_some(Outcome::WhiteWins("King reached the eighth rank"))
+                }
+                (false, false) => None,
+            }
+        }
+        Variant::Antichess => {
+            let team_white = position.white_to_move;
+            (!has_pieces(team_white)).then_some(if team_white {
+                Outcome::WhiteWins("All pieces lost")
+            } else {
+                Outcome::BlackWins("All pieces lost")
+            })
+        }
+        Variant::Horde => (!has_pieces(true)).then_some(Outcome::BlackWins("Horde destroyed")),
+        Variant::Crazyhouse => None,
+        Variant::Atomic => {
+            let has_king = |team_white: bool| {
+                position
+                    .pieces
+                    .iter()
+                    .any(|x| x.team_white == team_white && x.piece_type == PieceType::King)
+            };
+            if !has_king(true) {
+                Some(Outcome::BlackWins("King exploded"))
+            } else if !has_king(false) {
+                Some(Outcome::WhiteWins("King exploded"))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+//Result when the side to move has no moves at all, which only wins outright in antichess
+pub fn no_moves(position: &Position) -> Outcome {
+    if position.variant == Variant::Antichess {
+        return if position.white_to_move {
+            Outcome::WhiteWins("No moves left")
+        } else {
+            Outcome::BlackWins("No moves left")
+        };
+    }
+
+    if !in_check(position) {
+        Outcome::Draw("Stalemate")
+    } else if position.white_to_move {
+        Outcome::BlackWins("Checkmate")
+    } else {
+        Outcome::WhiteWins("Checkmate")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{captured, Variant};
+    use crate::bitboard::{bit, square_index};
+    use crate::movegen::Move;
+    use crate::position::Position;
+    use crate::PieceType;
+
+    fn captured_by(fen: &str, chess960: bool, mv: &str) -> Option<PieceType> {
+        let mut position = Position::from_fen(fen).unwrap();
+        position.chess960 = chess960;
+        captured(&position, Move::from_uci(&position, mv).unwrap())
+    }
+
+    #[test]
+    fn captures() {
+        assert!(
+            captured_by("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", false, "e4d5")
+                == Some(PieceType::Pawn)
+        );
+        assert!(
+            captured_by("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", false, "e5d6")
+                == Some(PieceType::Pawn)
+        );
+        assert!(captured_by("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", false, "e5e6").is_none());
+        //The king lands on its own rook
+        assert!(captured_by("4k3/8/8/8/8/8/8/4K2R w K - 0 1", true, "e1h1").is_none());
+    }
+
+    #[test]
+    fn explosions() {
+        //A knight that took on d5 goes up with the bishop beside it, the pawn is left standing
+        let position = Position::from_fen("4k3/8/2pb4/3N4/8/8/8/4K3 b - - 0 1").unwrap();
+        let d5 = square_index((3, 3));
+        assert_eq!(
+            Variant::Atomic.explosion(&position.board, d5),
+            bit((3, 3)) | bit((3, 2))
+        );
+        assert_eq!(Variant::Standard.explosion(&position.board, d5), 0);
+    }
+
+    #[test]
+    fn double_steps() {
+        assert!(Variant::Standard.double_step(true, 1, 8));
+        assert!(!Variant::Standard.double_step(true, 0, 8));
+        assert!(Variant::Horde.double_step(true, 0, 8));
+        assert!(!Variant::Horde.double_step(false, 7, 8));
+        assert!(!Variant::Gardner.double_step(true, 1, 5));
+    }
+}
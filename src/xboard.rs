@@ -0,0 +1,353 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::book::{Book, Pick};
+use crate::engine::{self, Difficulty, Limits, Rng, SearchInfo, MATE_SCORE};
+use crate::movegen::{make_move, outcome, unmake_move, Move, Undo};
+use crate::position::Position;
+use crate::tablebase::Tablebase;
+use crate::variant::{Variant, VARIANTS};
+
+//Commands that only change settings, so they can be handled while the engine thinks
+const SETTINGS_COMMANDS: [&str; 11] = [
+    "xboard", "protover", "accepted", "rejected", "time", "otim", "post", "nopost", "hard", "easy",
+    "computer",
+];
+
+struct XBoard {
+    position: Position,
+    //Moves played with what's needed to take them back, for undo and remove
+    history: Vec<(Move, Undo)>,
+    //In force mode the engine only keeps track of the moves it's given
+    force: bool,
+    engine_white: bool,
+    post: bool,
+    level: u8,
+    depth: Option<u8>,
+    //Fixed time per move set with st
+    move_time: Option<Duration>,
+    //Time control set with level, zero moves per session meaning the whole game
+    moves_per_session: u32,
+    increment: Duration,
+    //Engine's clock as last reported by time
+    clock: Option<Duration>,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<Option<Move>>>,
+    book: Option<Book>,
+    book_pick: Pick,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+//Mates are reported as 100000 plus the number of moves, as xboard expects
+fn format_score(score: i32) -> i32 {
+    if score > MATE_SCORE - 1000 {
+        100_000 + (MATE_SCORE - score + 1) / 2
+    } else if score < -MATE_SCORE + 1000 {
+        -100_000 - (MATE_SCORE + score) / 2
+    } else {
+        score
+    }
+}
+
+//ply score time nodes pv, with the time in centiseconds
+fn format_thinking(info: &SearchInfo) -> String {
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        format_score(info.score),
+        info.time.as_millis() / 10,
+        info.nodes,
+        info.pv
+            .iter()
+            .map(|x| x.to_uci())
+            .collect::<Vec<String>>()
+            .join(" ")
+    )
+}
+
+//Base time is given either in minutes or as minutes:seconds
+fn parse_base_time(text: &str) -> Option<Duration> {
+    let (minutes, seconds) = text.split_once(':').unwrap_or((text, "0"));
+    Some(Duration::from_secs(
+        minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?,
+    ))
+}
+
+impl XBoard {
+    fn new(
+        level: u8,
+        book: Option<Book>,
+        book_pick: Pick,
+        tablebase: Option<Arc<Tablebase>>,
+    ) -> Self {
+        XBoard {
+            position: Position::startpos(),
+            history: vec![],
+            force: false,
+            engine_white: false,
+            post: false,
+            level,
+            depth: None,
+            move_time: None,
+            moves_per_session: 0,
+            increment: Duration::ZERO,
+            clock: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+            book,
+            book_pick,
+            tablebase,
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        let time = match (self.move_time, self.clock) {
+            (Some(v), _) => Some(v),
+            (None, Some(remaining)) => {
+                let moves_to_go = if self.moves_per_session == 0 {
+                    engine::DEFAULT_MOVES_TO_GO
+                } else {
+                    self.moves_per_session
+                        - (self.position.fullmove_number - 1) % self.moves_per_session
+                };
+                Some(engine::time_for_move(
+                    remaining,
+                    self.increment,
+                    moves_to_go,
+                ))
+            }
+            (None, None) => None,
+        };
+
+        Limits {
+            depth: self.depth,
+            nodes: None,
+            time,
+            moves: None,
+        }
+    }
+
+    fn play(&mut self, mv: Move) {
+        let undo = make_move(&mut self.position, mv);
+        self.history.push((mv, undo));
+
+        if let Some(outcome) = outcome(&self.position) {
+            println!("{} {{{}}}", outcome.result(), outcome.reason());
+        }
+    }
+
+    fn think(&mut self) {
+        if outcome(&self.position).is_some() {
+            return;
+        }
+
+        self.engine_white = self.position.white_to_move;
+        self.stop = Arc::new(AtomicBool::new(false));
+
+        //Book and tablebase moves are played without searching
+        let known_move = self
+            .book
+            .as_ref()
+            .and_then(|x| x.pick(&self.position, self.book_pick, &mut Rng::new()))
+            .or_else(|| {
+                self.tablebase
+                    .as_ref()
+                    .and_then(|x| x.best_move(&self.position))
+                    .map(|x| x.0)
+            });
+        if let Some(mv) = known_move {
+            println!("move {}", mv.to_uci());
+            self.play(mv);
+            return;
+        }
+
+        let position = self.position.clone();
+        let difficulty = Difficulty::from_level(self.level);
+        let limits = self.limits();
+        let stop = self.stop.clone();
+        let post = self.post;
+
+        self.search = Some(std::thread::spawn(move || {
+            let result = engine::search(
+                &position,
+                &difficulty,
+                &limits,
+                &stop,
+                &mut Rng::new(),
+                &mut |info| {
+                    if post {
+                        println!("{}", format_thinking(info));
+                    }
+                },
+            );
+
+            if let Some(v) = result.best_move {
+                println!("move {}", v.to_uci());
+            }
+            result.best_move
+        }));
+    }
+
+    //Wait for the engine's move and play it on the board
+    fn finish_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            if let Some(mv) = search.join().unwrap() {
+                self.play(mv);
+            }
+        }
+    }
+
+    fn user_move(&mut self, text: &str) {
+        match Move::from_uci(&self.position, text) {
+            Some(mv) => {
+                self.play(mv);
+                if !self.force && self.position.white_to_move == self.engine_white {
+                    self.think();
+                }
+            }
+            None => println!("Illegal move: {text}"),
+        }
+    }
+
+    fn undo(&mut self, count: usize) {
+        for _ in 0..count {
+            if let Some((mv, undo)) = self.history.pop() {
+                unmake_move(&mut self.position, mv, undo);
+            }
+        }
+    }
+
+    //option Skill Level=5
+    fn set_option(&mut self, line: &str) {
+        let (name, value) = line.split_once('=').unwrap_or((line, ""));
+        if name.trim().eq_ignore_ascii_case("skill level") {
+            match value.trim().parse::<u8>() {
+                Ok(v) if (engine::MIN_LEVEL..=engine::MAX_LEVEL).contains(&v) => self.level = v,
+                _ => println!("Error (Skill Level must be between 1 and 10): {value}"),
+            }
+        } else {
+            println!("Error (unknown option): {name}");
+        }
+    }
+}
+
+pub fn run(level: u8, book: Option<Book>, book_pick: Pick, tablebase: Option<Arc<Tablebase>>) {
+    let mut xboard = XBoard::new(level, book, book_pick, tablebase);
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let Some(&command) = words.first() else {
+            continue;
+        };
+
+        //Move now, or the game the engine is thinking about is over anyway
+        if ["?", "new", "result"].contains(&command) {
+            xboard.stop.store(true, Ordering::Relaxed);
+        }
+        if !SETTINGS_COMMANDS.contains(&command) {
+            xboard.finish_search();
+        }
+
+        let argument = |i: usize| words.get(i).copied().unwrap_or("");
+
+        match command {
+            "xboard" | "accepted" | "rejected" | "hard" | "easy" | "computer" | "random" | "?" => {}
+            "protover" => println!(
+                "feature myname=\"chess\" setboard=1 usermove=1 ping=1 sigint=0 sigterm=0 \
+                 colors=0 analyze=0 variants=\"{}\" egt=\"syzygy\" \
+                 option=\"Skill Level -spin {} {} {}\" done=1",
+                VARIANTS.map(|x| x.xboard_name()).join(","),
+                xboard.level,
+                engine::MIN_LEVEL,
+                engine::MAX_LEVEL
+            ),
+            "new" => {
+                let post = xboard.post;
+                xboard = XBoard::new(
+                    xboard.level,
+                    xboard.book.take(),
+                    xboard.book_pick,
+                    xboard.tablebase.take(),
+                );
+                xboard.post = post;
+            }
+            //Comes after new, which goes back to standard chess
+            "variant" => match Variant::parse(argument(1)).map(Variant::start) {
+                Some(Ok(v)) => {
+                    xboard.position = v;
+                    xboard.history.clear();
+                }
+                Some(Err(e)) => println!("Error (variant): {e}"),
+                None => println!("Error (unknown variant): {}", argument(1)),
+            },
+            "setboard" => match Position::from_fen(&words[1..].join(" ")) {
+                Ok(mut v) => {
+                    if v.variant == Variant::Standard {
+                        v.variant = xboard.position.variant;
+                    }
+                    xboard.position = v;
+                    xboard.history.clear();
+                }
+                Err(e) => println!("tellusererror Illegal position: {e}"),
+            },
+            //egtpath syzygy <dirs>
+            "egtpath" if argument(1) == "syzygy" => match Tablebase::open(&words[2..].join(" ")) {
+                Ok(v) => xboard.tablebase = Some(Arc::new(v)),
+                Err(e) => println!("tellusererror {e}"),
+            },
+            "force" => xboard.force = true,
+            "go" => {
+                xboard.force = false;
+                xboard.think();
+            }
+            "playother" => {
+                xboard.force = false;
+                xboard.engine_white = !xboard.position.white_to_move;
+            }
+            "usermove" => xboard.user_move(argument(1)),
+            "level" => {
+                xboard.moves_per_session = argument(1).parse().unwrap_or(0);
+                xboard.increment =
+                    Duration::from_secs_f64(argument(3).parse::<f64>().unwrap_or(0.0).max(0.0));
+                xboard.clock = parse_base_time(argument(2));
+                xboard.move_time = None;
+            }
+            "st" => {
+                xboard.move_time = argument(1)
+                    .parse::<f64>()
+                    .ok()
+                    .map(|x| Duration::from_secs_f64(x.max(0.0)));
+            }
+            "sd" => xboard.depth = argument(1).parse::<u8>().ok().map(|x| x.max(1)),
+            "time" => {
+                xboard.clock = argument(1)
+                    .parse::<u64>()
+                    .ok()
+                    .map(|x| Duration::from_millis(x * 10));
+            }
+            "otim" => {}
+            "undo" => xboard.undo(1),
+            "remove" => xboard.undo(2),
+            "result" => xboard.force = true,
+            "post" => xboard.post = true,
+            "nopost" => xboard.post = false,
+            "ping" => println!("pong {}", argument(1)),
+            "option" => xboard.set_option(line.trim_start_matches("option").trim()),
+            "quit" => {
+                xboard.stop.store(true, Ordering::Relaxed);
+                return;
+            }
+            //Older interfaces send moves without the usermove prefix
+            _ if Move::from_uci(&xboard.position, command).is_some() => xboard.user_move(command),
+            _ => println!("Error (unknown command): {command}"),
+        }
+    }
+
+    xboard.finish_search();
+}
@@ -0,0 +1,138 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::Duration;
+
+//A chess uci process with its output read line by line in the background
+struct Session {
+    child: Child,
+    input: ChildStdin,
+    output: Receiver<String>,
+}
+
+impl Session {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_chess"))
+            .arg("uci")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let input = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, output) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Session {
+            child,
+            input,
+            output,
+        }
+    }
+
+    fn send(&mut self, command: &str) {
+        writeln!(self.input, "{command}").unwrap();
+    }
+
+    //Lines up to and including the first that starts with the prefix
+    fn until(&self, prefix: &str) -> Vec<String> {
+        let mut lines = vec![];
+        loop {
+            let line = self
+                .output
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap_or_else(|_| panic!("No {prefix} after {lines:?}"));
+            let done = line.starts_with(prefix);
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    fn quit(mut self) {
+        self.send("quit");
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn scripted_session() {
+    let mut session = Session::start();
+    session.send("uci");
+    let lines = session.until("uciok");
+    assert!(lines.iter().any(|x| x.starts_with("id name")));
+    assert!(lines
+        .iter()
+        .any(|x| x.starts_with("option name UCI_Variant")));
+
+    session.send("isready");
+    session.until("readyok");
+
+    session.send("ucinewgame");
+    session.send("position startpos moves e2e4 e7e5 g1f3");
+    session.send("go depth 2");
+    let bestmove = session.until("bestmove").pop().unwrap();
+    assert_ne!(bestmove, "bestmove 0000");
+
+    //Mate in one, searched only among the moves asked for
+    session.send("position fen 7k/8/6K1/8/8/8/8/R7 w - - 0 1");
+    session.send("go depth 3 searchmoves a1a7 a1a8");
+    let lines = session.until("bestmove");
+    assert_eq!(lines.last().unwrap(), "bestmove a1a8");
+    assert!(lines.iter().any(|x| x.contains("score mate 1")));
+
+    session.send("position startpos");
+    session.send("go perft 3");
+    assert_eq!(
+        session.until("Nodes searched").last().unwrap(),
+        "Nodes searched: 8902"
+    );
+
+    session.quit();
+}
+
+#[test]
+fn malformed_options_are_ignored() {
+    let mut session = Session::start();
+    for command in [
+        "setoption value 3",
+        "setoption",
+        "setoption name",
+        "setoption value 3 name Skill Level",
+        "setoption name Skill Level value",
+    ] {
+        session.send(command);
+    }
+    session.send("isready");
+    session.until("readyok");
+    session.quit();
+}
+
+#[test]
+fn ponderhit_switches_to_the_clock() {
+    let mut session = Session::start();
+    session.send("position startpos");
+    session.send("go ponder wtime 1000 btime 1000");
+
+    //Pondering goes on until the opponent's move is known
+    std::thread::sleep(Duration::from_millis(500));
+    let early = session.output.try_iter().collect::<Vec<String>>();
+    assert!(
+        !early.iter().any(|x| x.starts_with("bestmove")),
+        "{early:?}"
+    );
+
+    session.send("ponderhit");
+    session.until("bestmove");
+    session.quit();
+}